@@ -12,6 +12,22 @@ impl App {
             Self::Echo(app) => app.execute(op),
         }
     }
+
+    // the snapshot captures the state after every executed op, so protocols can compact the
+    // ops out of their logs
+    pub fn snapshot(&self) -> Box<[u8]> {
+        match self {
+            Self::Null(app) => app.snapshot(),
+            Self::Echo(app) => app.snapshot(),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &[u8]) {
+        match self {
+            Self::Null(app) => app.restore(snapshot),
+            Self::Echo(app) => app.restore(snapshot),
+        }
+    }
 }
 
 impl Protocol<&'_ [u8]> for App {
@@ -28,6 +44,12 @@ impl Null {
     fn execute(&mut self, _: &[u8]) -> Box<[u8]> {
         Default::default()
     }
+
+    fn snapshot(&self) -> Box<[u8]> {
+        Default::default()
+    }
+
+    fn restore(&mut self, _: &[u8]) {}
 }
 
 pub struct Echo;
@@ -36,4 +58,10 @@ impl Echo {
     fn execute(&mut self, op: &[u8]) -> Box<[u8]> {
         op.to_owned().into()
     }
+
+    fn snapshot(&self) -> Box<[u8]> {
+        Default::default()
+    }
+
+    fn restore(&mut self, _: &[u8]) {}
 }
@@ -0,0 +1,254 @@
+use std::{
+    env::{self, args},
+    iter::{repeat_with, RepeatWith},
+    net::{IpAddr, SocketAddr, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        Arc,
+    },
+    thread::{available_parallelism, sleep, spawn},
+    time::Duration,
+};
+
+use crossbeam::channel;
+use nix::{
+    sys::signal::{kill, Signal},
+    unistd::Pid,
+};
+use serde::de::DeserializeOwned;
+
+use crate::{
+    node::{ClientEffect, ClientEvent, Lifecycle, Workload, WorkloadMode},
+    protocol::{Batched, Composite, Generate},
+    set_affinity, udp,
+    unreplicated::{MessageRef, ZeroCopyReplica},
+    NodeAddr, NodeEffect, NodeEvent, Protocol,
+};
+
+// the deployment shared by the benchmark binaries of all protocols
+// `<bin> replica <id> -- <replica ip>...` and `<bin> client -- <replica ip>...`
+
+pub const REPLICA_PORT: u16 = 5000;
+
+// the number of receive threads of replica, each with its own `SO_REUSEPORT` socket and core,
+// which is overridden by the `NUM_RX` environment variable
+const DEFAULT_NUM_RX: usize = 2;
+
+fn num_rx() -> usize {
+    env::var("NUM_RX").map_or(DEFAULT_NUM_RX, |num| num.parse().unwrap())
+}
+
+pub fn replica_addrs() -> Box<[SocketAddr]> {
+    args()
+        .skip_while(|arg| arg != "--")
+        .skip(1)
+        .map(|ip| SocketAddr::from((ip.parse::<IpAddr>().unwrap(), REPLICA_PORT)))
+        .collect()
+}
+
+// the broadcast group of replica `id`, i.e. every other replica
+pub fn peers(id: u32, replica_addrs: &[SocketAddr]) -> Box<[SocketAddr]> {
+    replica_addrs
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| i != id as usize)
+        .map(|(_, &addr)| addr)
+        .collect()
+}
+
+pub fn node_addrs(addrs: &[SocketAddr]) -> Box<[NodeAddr]> {
+    addrs.iter().copied().map(NodeAddr::Socket).collect()
+}
+
+type Ops = RepeatWith<fn() -> Box<[u8]>>;
+
+// run a closed-loop client against `replica_addrs`, print the throughput and latencies of the
+// measured 10 seconds, and return the workload for protocol specific statistics
+// `new_client` is called with the client's own address
+pub fn client<C, M>(
+    replica_addrs: Box<[SocketAddr]>,
+    new_client: impl FnOnce(NodeAddr) -> C,
+) -> Workload<C, Ops>
+where
+    C: Protocol<ClientEvent<M>> + Send + 'static,
+    C::Effect: Composite<Atom = ClientEffect<M>>,
+    M: serde::Serialize + DeserializeOwned + Send + 'static,
+{
+    crate::capture_interrupt();
+
+    let socket = Arc::new(udp::client_socket(replica_addrs[0]));
+    udp::init_socket(&socket);
+    let mode = Arc::new(AtomicU8::new(WorkloadMode::Discard as _));
+    let mut node = Workload::new_benchmark(
+        new_client(NodeAddr::Socket(socket.local_addr().unwrap())),
+        repeat_with(Default::default as fn() -> _),
+        mode.clone(),
+    );
+
+    let message_channel = channel::unbounded();
+    let mut rx = udp::Rx(socket.clone());
+    let _rx =
+        spawn(move || rx.deploy(&mut udp::Deserialize::<M>::default().then(message_channel.0)));
+
+    let running = Arc::new(AtomicBool::new(false));
+    let node = spawn({
+        let running = running.clone();
+        let tx = udp::Tx::new(socket, replica_addrs);
+        // no more receiver other than the moved one
+        // just keep one receiver always connected to workaround `_rx` thread
+        #[allow(clippy::redundant_clone)]
+        let event_channel = message_channel.1.clone();
+        move || {
+            let mut lifecycle = Lifecycle::new(event_channel, running);
+            let timer = lifecycle.timer();
+            lifecycle.deploy(
+                &mut node
+                    .borrow_mut()
+                    .each_then(timer.each_then(udp::Serialize::default().each_then(tx))),
+            );
+            node
+        }
+    });
+
+    sleep(Duration::from_secs(2)); // warm up
+    mode.store(WorkloadMode::Benchmark as _, Ordering::SeqCst);
+    sleep(Duration::from_secs(10));
+    mode.store(WorkloadMode::Discard as _, Ordering::SeqCst);
+    sleep(Duration::from_secs(2)); // cool down
+
+    kill(Pid::from_raw(0), Signal::SIGINT).unwrap();
+    running.store(false, Ordering::SeqCst);
+
+    let workload = node.join().unwrap();
+    let mut latencies = workload.latencies.clone();
+    println!("{}", latencies.len() as f32 / 10.);
+    if !latencies.is_empty() {
+        latencies.sort_unstable();
+        println!(
+            "50th {:?} 99th {:?}",
+            latencies[latencies.len() / 2],
+            latencies[latencies.len() * 99 / 100]
+        )
+    }
+    workload
+}
+
+// run the replica on the port, until interrupted
+// there are `num_rx()` receive threads and one node thread, and the effects are sent as in
+// `spawn_tx` by the remaining cores
+pub fn replica<N, M>(node: N, broadcast: Box<[SocketAddr]>)
+where
+    N: Protocol<NodeEvent<M>> + Send + 'static,
+    N::Effect: Composite<Atom = NodeEffect<M>>,
+    M: serde::Serialize + DeserializeOwned + Send + 'static,
+{
+    crate::capture_interrupt();
+
+    let num_rx = num_rx();
+    assert!(num_rx > 0);
+    let sockets = udp::reuse_port_sockets(([0, 0, 0, 0], REPLICA_PORT).into(), num_rx)
+        .into_iter()
+        .map(Arc::new)
+        .collect::<Vec<_>>();
+
+    let message_channel = channel::unbounded();
+    let rx = sockets
+        .iter()
+        .enumerate()
+        .map(|(i, socket)| {
+            udp::init_socket(socket);
+            #[cfg(not(feature = "io-uring"))]
+            let mut rx = udp::Rx(socket.clone());
+            #[cfg(feature = "io-uring")]
+            let mut rx = crate::uring::Rx(socket.clone());
+            let message_channel = message_channel.0.clone();
+            spawn(move || {
+                set_affinity(i);
+                rx.deploy(&mut udp::Deserialize::<M>::default().then(message_channel))
+            })
+        })
+        .collect::<Vec<_>>();
+    // so the node sees disconnection once all receive threads exit
+    drop(message_channel.0);
+
+    let effect_channel = channel::unbounded();
+    let _node = spawn(move || {
+        set_affinity(num_rx);
+        let mut lifecycle = Lifecycle::new(message_channel.1, Default::default());
+        let timer = lifecycle.timer();
+        lifecycle.deploy(&mut node.each_then(timer.each_then(effect_channel.0)))
+    });
+
+    spawn_tx(effect_channel.1, &sockets, broadcast, num_rx + 1);
+
+    for rx in rx {
+        rx.join().unwrap();
+    }
+}
+
+// run the unreplicated replica with the zero-copy path until interrupted, i.e. the messages are
+// decoded with `udp::DeserializeBorrowed` and handled by the node on the only receive thread, and
+// the effects are sent by the remaining cores as in `replica`
+// the node is never ticked and its timers are never handled on the receive thread, so the path is
+// limited to the unreplicated replica, which uses neither
+pub fn zero_copy_replica(mut node: ZeroCopyReplica) {
+    crate::capture_interrupt();
+
+    let socket = Arc::new(
+        udp::reuse_port_sockets(([0, 0, 0, 0], REPLICA_PORT).into(), 1)
+            .pop()
+            .unwrap(),
+    );
+    udp::init_socket(&socket);
+    let effect_channel = channel::unbounded();
+    let rx = spawn({
+        #[cfg(not(feature = "io-uring"))]
+        let mut rx = udp::Rx(socket.clone());
+        #[cfg(feature = "io-uring")]
+        let mut rx = crate::uring::Rx(socket.clone());
+        move || {
+            set_affinity(0);
+            let mut node = node.borrow_mut().each_then(effect_channel.0);
+            node.update(NodeEvent::Init);
+            rx.deploy(
+                &mut udp::DeserializeBorrowed::<MessageRef<'static>>::default().each_then(node),
+            )
+        }
+    });
+
+    spawn_tx(effect_channel.1, &[socket], Default::default(), 1);
+    rx.join().unwrap();
+}
+
+// serialize and send the effects on every core from `first_core` except the last one, which is
+// saved for IRQ handling
+// effects go out through whichever socket, they share the same local address
+fn spawn_tx<M>(
+    effect_channel: channel::Receiver<NodeEffect<M>>,
+    sockets: &[Arc<UdpSocket>],
+    broadcast: Box<[SocketAddr]>,
+    first_core: usize,
+) where
+    M: serde::Serialize + Send + 'static,
+{
+    let irq_core = available_parallelism().unwrap().get() - 1;
+    assert!(first_core < irq_core, "no core left for sending");
+    for i in first_core..irq_core {
+        let mut effect_channel = Batched(effect_channel.clone(), udp::BATCH_SIZE);
+        let socket = sockets[i % sockets.len()].clone();
+        let broadcast = broadcast.clone();
+        let _tx = spawn(move || {
+            set_affinity(i);
+            let mut serialize = udp::Serialize::default();
+            effect_channel.deploy(
+                &mut (move |effects: Vec<_>| {
+                    effects
+                        .into_iter()
+                        .filter_map(|effect| serialize.update(effect))
+                        .collect::<Vec<_>>()
+                })
+                .then(udp::Tx::new(socket, broadcast)),
+            )
+        });
+    }
+}
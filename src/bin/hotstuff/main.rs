@@ -0,0 +1,36 @@
+use std::env::args;
+
+use dsys::{
+    app, bench,
+    crypto::{public_keys, secret_key},
+    hotstuff::{Client, Replica},
+    App,
+};
+use rand::random;
+
+fn main() {
+    let replica_addrs = bench::replica_addrs();
+    match args().nth(1).as_deref() {
+        Some("replica") => {
+            let id = args().nth(2).unwrap().parse().unwrap();
+            let node = Replica::new(
+                id,
+                bench::node_addrs(&replica_addrs),
+                secret_key(id),
+                public_keys(replica_addrs.len()),
+                App::Null(app::Null),
+            );
+            bench::replica(node, bench::peers(id, &replica_addrs))
+        }
+        Some("client") => {
+            let public_keys = public_keys(replica_addrs.len());
+            let workload = bench::client(replica_addrs, |addr| {
+                Client::new(random(), addr, public_keys)
+            });
+            if !workload.node.state.resend_stats.is_empty() {
+                println!("resend {:?}", workload.node.state.resend_stats);
+            }
+        }
+        _ => panic!(),
+    }
+}
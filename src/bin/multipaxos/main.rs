@@ -0,0 +1,28 @@
+use std::env::args;
+
+use dsys::{
+    app, bench,
+    multipaxos::{Client, Replica},
+    App,
+};
+use rand::random;
+
+fn main() {
+    let replica_addrs = bench::replica_addrs();
+    match args().nth(1).as_deref() {
+        Some("replica") => {
+            let id = args().nth(2).unwrap().parse().unwrap();
+            let node = Replica::new(id, bench::node_addrs(&replica_addrs), App::Null(app::Null));
+            bench::replica(node, bench::peers(id, &replica_addrs))
+        }
+        Some("client") => {
+            let replicas = bench::node_addrs(&replica_addrs);
+            let workload =
+                bench::client(replica_addrs, |addr| Client::new(random(), addr, replicas));
+            if !workload.node.state.resend_stats.is_empty() {
+                println!("resend {:?}", workload.node.state.resend_stats);
+            }
+        }
+        _ => panic!(),
+    }
+}
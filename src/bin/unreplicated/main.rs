@@ -5,7 +5,8 @@ pub mod replica;
 
 fn main() {
     match args().nth(1).as_deref() {
-        Some("replica") => replica::main(),
+        // decode and handle requests on the receiving thread without copying if `--zero-copy`
+        Some("replica") => replica::main(args().nth(2).as_deref() == Some("--zero-copy")),
         Some("client") => client::main(
             args()
                 .nth(2)
@@ -14,6 +15,8 @@ fn main() {
                 .parse()
                 .unwrap(),
         ),
+        Some("replay") => client::replay(args().nth(2).unwrap()),
+        Some("replay-replica") => replica::replay(args().nth(2).unwrap()),
         _ => panic!(),
     }
 }
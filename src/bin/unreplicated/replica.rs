@@ -1,53 +1,33 @@
 use std::{
-    convert::identity,
-    net::UdpSocket,
-    sync::Arc,
-    thread::{available_parallelism, spawn},
+    env,
+    fs::File,
+    io::{BufReader, BufWriter},
 };
 
-use crossbeam::channel;
 use dsys::{
-    app,
-    node::Lifecycle,
+    app, bench,
     protocol::Generate,
-    set_affinity, udp,
-    unreplicated::{Message, Replica},
-    App, Protocol,
+    trace::{Record, Replay},
+    unreplicated::{Message, Replica, ZeroCopyReplica},
+    App, NodeEvent, Protocol,
 };
 
-pub fn main() {
-    dsys::capture_interrupt();
-
-    let socket = Arc::new(UdpSocket::bind("0.0.0.0:5000").unwrap());
-    udp::init_socket(&socket);
-    let node = Replica::new(App::Null(app::Null));
-
-    let message_channel = channel::unbounded();
-    let mut rx = udp::Rx(socket.clone());
-    let rx = spawn(move || {
-        set_affinity(0);
-        rx.deploy(&mut udp::Deserialize::<Message>::default().then(message_channel.0))
-    });
-
-    let effect_channel = channel::unbounded();
-    let _node = spawn(move || {
-        set_affinity(1);
-        Lifecycle::new(message_channel.1, Default::default())
-            .deploy(&mut node.then(effect_channel.0))
-    });
-
-    // save the last parallelism for IRQ handling
-    for i in 2..available_parallelism().unwrap().get() - 1 {
-        let mut effect_channel = effect_channel.1.clone();
-        let socket = socket.clone();
-        let _tx = spawn(move || {
-            set_affinity(i);
-            effect_channel.deploy(
-                &mut identity
-                    .then(udp::Serialize::default().then(udp::Tx::new(socket, Default::default()))),
-            )
-        });
+pub fn main(zero_copy: bool) {
+    let replica = Replica::new(App::Null(app::Null));
+    if zero_copy {
+        bench::zero_copy_replica(ZeroCopyReplica(replica))
+    } else if let Ok(path) = env::var("TRACE") {
+        // record the replica events for `replay-replica`, the same way as the client's
+        let trace = BufWriter::new(File::create(path).unwrap());
+        bench::replica(Record::new(replica, trace), Default::default())
+    } else {
+        bench::replica(replica, Default::default())
     }
+}
 
-    rx.join().unwrap();
+pub fn replay(path: String) {
+    let mut replay =
+        Replay::<_, NodeEvent<Message>>::new(BufReader::new(File::open(path).unwrap()));
+    let mut replica = Replica::new(App::Null(app::Null));
+    replay.deploy(&mut Protocol::borrow_mut(&mut replica).then(|_: Option<_>| {}));
 }
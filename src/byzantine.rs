@@ -0,0 +1,78 @@
+use crate::{protocol::Composite, NodeEffect, NodeEvent, Protocol};
+
+// the adversary controls a node's network interface, i.e., everything the node receives and sends
+// it knows whatever the test gives to it (e.g. the node's secret key), so it can sign forged
+// messages as the node
+pub trait Adversary<M> {
+    // return false to drop the event before the node sees it
+    fn receive(&mut self, _event: &NodeEvent<M>) -> bool {
+        true
+    }
+
+    // rewrite an outgoing effect of the node, e.g., drop it, tamper with it, or equivocate by
+    // sending different messages to different destinations
+    fn send(&mut self, effect: NodeEffect<M>) -> Vec<NodeEffect<M>> {
+        vec![effect]
+    }
+
+    // inject effects on every tick regardless of the node, e.g., replay old messages
+    fn tick(&mut self) -> Vec<NodeEffect<M>> {
+        Vec::new()
+    }
+}
+
+// honest
+impl<M> Adversary<M> for () {}
+
+impl<M, A> Adversary<M> for Box<A>
+where
+    A: Adversary<M> + ?Sized,
+{
+    fn receive(&mut self, event: &NodeEvent<M>) -> bool {
+        (**self).receive(event)
+    }
+
+    fn send(&mut self, effect: NodeEffect<M>) -> Vec<NodeEffect<M>> {
+        (**self).send(effect)
+    }
+
+    fn tick(&mut self) -> Vec<NodeEffect<M>> {
+        (**self).tick()
+    }
+}
+
+pub struct Byzantine<N, A> {
+    pub node: N,
+    pub adversary: A,
+}
+
+impl<N, A> Byzantine<N, A> {
+    pub fn new(node: N, adversary: A) -> Self {
+        Self { node, adversary }
+    }
+}
+
+impl<N, A, M> Protocol<NodeEvent<M>> for Byzantine<N, A>
+where
+    N: Protocol<NodeEvent<M>>,
+    N::Effect: Composite<Atom = NodeEffect<M>>,
+    A: Adversary<M>,
+{
+    type Effect = Vec<NodeEffect<M>>;
+
+    fn update(&mut self, event: NodeEvent<M>) -> Self::Effect {
+        let mut effects = if matches!(event, NodeEvent::Tick) {
+            self.adversary.tick()
+        } else {
+            Vec::new()
+        };
+        if self.adversary.receive(&event) {
+            effects.extend(
+                self.node
+                    .update(event)
+                    .map(|effect| self.adversary.send(effect)),
+            );
+        }
+        effects
+    }
+}
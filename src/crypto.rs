@@ -1,11 +1,21 @@
 use std::mem::take;
 
 use bincode::Options;
-use secp256k1::{ecdsa, hashes::sha256, All, Message, PublicKey, Secp256k1, SecretKey};
+use secp256k1::{
+    ecdsa,
+    hashes::{sha256, Hash},
+    All, Message, PublicKey, Secp256k1, SecretKey,
+};
 use serde::Serialize;
 
 pub type Signature = ([u8; 32], [u8; 32]);
 
+pub type Digest = [u8; 32];
+
+pub fn digest(message: &impl Serialize) -> Digest {
+    sha256::Hash::hash(&bincode::options().serialize(message).unwrap()).into_inner()
+}
+
 pub trait CryptoMessage: Serialize {
     fn signature(&mut self) -> Option<&mut Signature>;
 }
@@ -14,6 +24,19 @@ thread_local! {
     static SECP: Secp256k1<All> = Secp256k1::new();
 }
 
+// the key pair of every replica is derived from its id, which is enough for evaluation and testing
+pub fn secret_key(id: u32) -> SecretKey {
+    SecretKey::from_slice(&[id as u8 + 1; 32]).unwrap()
+}
+
+pub fn public_keys(num_replica: usize) -> Box<[PublicKey]> {
+    SECP.with(|secp| {
+        (0..num_replica as u32)
+            .map(|id| PublicKey::from_secret_key(secp, &secret_key(id)))
+            .collect()
+    })
+}
+
 pub fn sign(message: &mut impl CryptoMessage, secret_key: &SecretKey) {
     let digest =
         Message::from_hashed_data::<sha256::Hash>(&bincode::options().serialize(&message).unwrap());
@@ -40,18 +63,14 @@ where
     bytes[32..].copy_from_slice(&signature.1);
     let digest =
         Message::from_hashed_data::<sha256::Hash>(&bincode::options().serialize(&message).unwrap());
-    SECP.with(|secp| {
-        secp.verify_ecdsa(
-            &digest,
-            &ecdsa::Signature::from_compact(&bytes).unwrap(),
-            public_key,
-        )
-    })
-    .ok()
-    .map(|_| {
-        *message.signature().unwrap() = signature;
-        message
-    })
+    // forged signature may not even be well-formed
+    let ecdsa_signature = ecdsa::Signature::from_compact(&bytes).ok()?;
+    SECP.with(|secp| secp.verify_ecdsa(&digest, &ecdsa_signature, public_key))
+        .ok()
+        .map(|_| {
+            *message.signature().unwrap() = signature;
+            message
+        })
 }
 
 #[cfg(test)]
@@ -0,0 +1,826 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use secp256k1::{PublicKey, SecretKey};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app::App,
+    crypto::{digest, sign, verify, CryptoMessage, Digest, Signature},
+    node::{ClientEffect, ClientEvent, ClientState, Request, UnexpectedMessage},
+    NodeAddr, NodeEffect, NodeEvent, Protocol,
+};
+
+// client authentication is out of scope, so requests are not signed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reply {
+    seq: u32,
+    replica_id: u32,
+    result: Box<[u8]>,
+    signature: Signature,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuorumCert {
+    view: u32,
+    block: Digest,
+    // the signatures of the `Vote`s
+    signatures: Vec<(u32, Signature)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Block {
+    view: u32,
+    parent: Digest,
+    justify: QuorumCert,
+    requests: Vec<Request>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Proposal {
+    block: Block,
+    signature: Signature,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Vote {
+    view: u32,
+    block: Digest,
+    replica_id: u32,
+    signature: Signature,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewView {
+    view: u32,
+    qc: QuorumCert,
+    replica_id: u32,
+    signature: Signature,
+}
+
+// the block sync of a replica that missed a proposal, which is not signed because the block is
+// checked against the requested digest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetBlock {
+    block: Digest,
+    replica_id: u32,
+}
+
+macro_rules! impl_crypto_message {
+    ($($message:ident),*) => {
+        $(
+            impl CryptoMessage for $message {
+                fn signature(&mut self) -> Option<&mut Signature> {
+                    Some(&mut self.signature)
+                }
+            }
+        )*
+    };
+}
+
+impl_crypto_message!(Reply, Proposal, Vote, NewView);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Message {
+    Request(Request),
+    Reply(Reply),
+    Proposal(Proposal),
+    Vote(Vote),
+    NewView(NewView),
+    GetBlock(GetBlock),
+    Block(Block),
+}
+
+fn f(num_replica: usize) -> usize {
+    (num_replica - 1) / 3
+}
+
+pub struct Client {
+    pub state: ClientState,
+    num_replica: usize,
+    public_keys: Box<[PublicKey]>,
+    replies: HashMap<u32, Reply>,
+}
+
+impl Client {
+    pub fn new(id: u32, addr: NodeAddr, public_keys: Box<[PublicKey]>) -> Self {
+        Self {
+            state: ClientState::new(id, addr),
+            num_replica: public_keys.len(),
+            public_keys,
+            replies: Default::default(),
+        }
+    }
+}
+
+impl Protocol<ClientEvent<Message>> for Client {
+    type Effect = Option<ClientEffect<Message>>;
+
+    fn update(&mut self, event: ClientEvent<Message>) -> Self::Effect {
+        match event {
+            // leader rotates every view, so requests always go to every replica
+            ClientEvent::Op(op) => {
+                let request = self.state.invoke(op);
+                self.replies.clear();
+                Some(ClientEffect::Node(NodeEffect::Broadcast(Message::Request(
+                    request,
+                ))))
+            }
+            ClientEvent::Node(NodeEvent::Init) => None,
+            ClientEvent::Node(NodeEvent::Tick) => {
+                let request = self.state.tick()?;
+                Some(ClientEffect::Node(NodeEffect::Broadcast(Message::Request(
+                    request,
+                ))))
+            }
+            ClientEvent::Node(NodeEvent::Handle(Message::Reply(reply))) => {
+                if !self.state.is_outstanding(reply.seq) {
+                    return None;
+                }
+                let public_key = self.public_keys.get(reply.replica_id as usize)?;
+                let reply = verify(reply, public_key)?;
+                self.replies.insert(reply.replica_id, reply.clone());
+                let num_match = self
+                    .replies
+                    .values()
+                    .filter(|other| other.result == reply.result)
+                    .count();
+                if num_match <= f(self.num_replica) {
+                    return None;
+                }
+                self.state.complete();
+                Some(ClientEffect::Result(reply.result))
+            }
+            ClientEvent::Node(NodeEvent::Handle(_)) => None,
+            ClientEvent::Node(NodeEvent::Timeout(_)) => None,
+        }
+    }
+}
+
+pub struct Replica {
+    id: u32,
+    replicas: Box<[NodeAddr]>,
+    secret_key: SecretKey,
+    public_keys: Box<[PublicKey]>,
+    app: App,
+    view: u32,
+    genesis: Digest,
+    // the last view the replica voted in
+    voted_view: u32,
+    blocks: HashMap<Digest, Block>,
+    locked: Digest,
+    // (view, digest) of the latest block committed by the three-chain rule, which is executed
+    // along with its ancestors as soon as none of them is missing
+    committed: (u32, Digest),
+    executed: Digest,
+    // the blocks that are requested with `GetBlock`
+    missing: HashSet<Digest>,
+    qc_high: QuorumCert,
+    // as leader, the last view a block is proposed in
+    proposed_view: u32,
+    votes: HashMap<Digest, BTreeMap<u32, Signature>>,
+    new_views: BTreeMap<u32, HashSet<u32>>,
+    // the latest request of each client that is not executed yet
+    pending: BTreeMap<u32, Request>,
+    client_table: HashMap<u32, Reply>,
+    // ticks since the last progress of the view while there are pending requests
+    ticked: u32,
+}
+
+const VIEW_TICKS: u32 = 10;
+// new views are only collected for this many views after the current one, so faulty replicas
+// cannot make `new_views` grow without bound
+const NEW_VIEW_WINDOW: u32 = 10;
+
+impl Replica {
+    pub fn new(
+        id: u32,
+        replicas: Box<[NodeAddr]>,
+        secret_key: SecretKey,
+        public_keys: Box<[PublicKey]>,
+        app: App,
+    ) -> Self {
+        assert!((id as usize) < replicas.len());
+        assert_eq!(replicas.len(), public_keys.len());
+        // the genesis block justifies itself, and its certificate is the only one without
+        // signatures
+        let genesis = Block {
+            view: 0,
+            parent: Default::default(),
+            justify: QuorumCert {
+                view: 0,
+                block: Default::default(),
+                signatures: Default::default(),
+            },
+            requests: Default::default(),
+        };
+        let genesis_digest = digest(&genesis);
+        let qc_high = QuorumCert {
+            view: 0,
+            block: genesis_digest,
+            signatures: Default::default(),
+        };
+        let genesis = Block {
+            justify: qc_high.clone(),
+            ..genesis
+        };
+        Self {
+            id,
+            replicas,
+            secret_key,
+            public_keys,
+            app,
+            view: 1,
+            genesis: genesis_digest,
+            voted_view: 0,
+            blocks: [(genesis_digest, genesis)].into_iter().collect(),
+            locked: genesis_digest,
+            committed: (0, genesis_digest),
+            executed: genesis_digest,
+            missing: Default::default(),
+            qc_high,
+            proposed_view: 0,
+            votes: Default::default(),
+            new_views: Default::default(),
+            pending: Default::default(),
+            client_table: Default::default(),
+            ticked: 0,
+        }
+    }
+
+    fn f(&self) -> usize {
+        f(self.replicas.len())
+    }
+
+    fn leader(&self, view: u32) -> u32 {
+        view % self.replicas.len() as u32
+    }
+
+    fn sign<M>(&self, mut message: M) -> M
+    where
+        M: CryptoMessage,
+    {
+        sign(&mut message, &self.secret_key);
+        message
+    }
+
+    fn verify<M>(&self, message: M, replica_id: u32) -> Option<M>
+    where
+        M: CryptoMessage,
+    {
+        verify(message, self.public_keys.get(replica_id as usize)?)
+    }
+
+    fn verify_qc(&self, qc: &QuorumCert) -> bool {
+        if qc.view == 0 {
+            return qc.block == self.genesis;
+        }
+        let mut replicas = HashSet::new();
+        for &(replica_id, signature) in &qc.signatures {
+            let vote = Vote {
+                view: qc.view,
+                block: qc.block,
+                replica_id,
+                signature,
+            };
+            if !replicas.insert(replica_id) || self.verify(vote, replica_id).is_none() {
+                return false;
+            }
+        }
+        replicas.len() > 2 * self.f()
+    }
+
+    fn block_view(&self, digest: &Digest) -> Option<u32> {
+        self.blocks.get(digest).map(|block| block.view)
+    }
+
+    fn extends(&self, mut digest: Digest, ancestor: Digest) -> bool {
+        let Some(ancestor_view) = self.block_view(&ancestor) else {
+            return false;
+        };
+        while let Some(block) = self.blocks.get(&digest) {
+            if digest == ancestor {
+                return true;
+            }
+            if block.view <= ancestor_view {
+                return false;
+            }
+            digest = block.parent;
+        }
+        false
+    }
+
+    fn update_qc_high(&mut self, qc: QuorumCert) {
+        if qc.view > self.qc_high.view {
+            self.qc_high = qc;
+        }
+    }
+
+    fn handle_request(&mut self, request: Request) -> Vec<NodeEffect<Message>> {
+        match self.client_table.get(&request.client_id) {
+            Some(reply) if reply.seq > request.seq => return Vec::new(),
+            Some(reply) if reply.seq == request.seq => {
+                return vec![NodeEffect::Send(
+                    request.client_addr,
+                    Message::Reply(reply.clone()),
+                )]
+            }
+            _ => {}
+        }
+        match self.pending.get(&request.client_id) {
+            Some(other) if other.seq >= request.seq => {}
+            _ => {
+                self.pending.insert(request.client_id, request);
+            }
+        }
+        self.propose()
+    }
+
+    fn propose(&mut self) -> Vec<NodeEffect<Message>> {
+        if self.leader(self.view) != self.id || self.proposed_view >= self.view {
+            return Vec::new();
+        }
+        let ready = self.qc_high.view + 1 == self.view
+            || matches!(self.new_views.get(&self.view), Some(replicas) if replicas.len() > 2 * self.f());
+        if !ready || !self.blocks.contains_key(&self.qc_high.block) {
+            return Vec::new();
+        }
+
+        // keep extending the chain until every proposed request is committed
+        let mut proposed = HashSet::new();
+        let mut digest = self.qc_high.block;
+        let executed_view = self.block_view(&self.executed).unwrap();
+        while let Some(block) = self.blocks.get(&digest) {
+            if block.view <= executed_view {
+                break;
+            }
+            proposed.extend(
+                block
+                    .requests
+                    .iter()
+                    .map(|request| (request.client_id, request.seq)),
+            );
+            digest = block.parent;
+        }
+        let requests = self
+            .pending
+            .values()
+            .filter(|request| !proposed.contains(&(request.client_id, request.seq)))
+            .cloned()
+            .collect::<Vec<_>>();
+        if requests.is_empty() && proposed.is_empty() {
+            return Vec::new();
+        }
+
+        self.proposed_view = self.view;
+        let proposal = self.sign(Proposal {
+            block: Block {
+                view: self.view,
+                parent: self.qc_high.block,
+                justify: self.qc_high.clone(),
+                requests,
+            },
+            signature: Default::default(),
+        });
+        // effects are decomposed from the back, so place the proposal last to have it sent ahead of
+        // the leader's own vote, which the next leader drops if the block is still unknown to it
+        let mut effects = self.on_proposal(proposal.block.clone());
+        effects.push(NodeEffect::Broadcast(Message::Proposal(proposal)));
+        effects
+    }
+
+    fn handle_proposal(&mut self, proposal: Proposal) -> Vec<NodeEffect<Message>> {
+        // a proposal of a passed view is still accepted, because it may be the block that the
+        // certificate of the current view refers to
+        let block = &proposal.block;
+        if block.justify.view >= block.view || !self.verify_qc(&block.justify) {
+            return Vec::new();
+        }
+        let leader = self.leader(block.view);
+        let Some(proposal) = self.verify(proposal, leader) else {
+            return Vec::new();
+        };
+        self.on_proposal(proposal.block)
+    }
+
+    fn on_proposal(&mut self, block: Block) -> Vec<NodeEffect<Message>> {
+        let block_digest = digest(&block);
+        let view = block.view;
+        // the safety rule and the liveness rule
+        let safe = self.extends(block.parent, self.locked)
+            || matches!(
+                self.block_view(&block.justify.block),
+                Some(justify_view) if justify_view > self.block_view(&self.locked).unwrap()
+            );
+        self.blocks.insert(block_digest, block);
+        let mut effects = self.update(block_digest);
+        self.view = self.view.max(view + 1);
+        self.ticked = 0;
+        if safe && view > self.voted_view {
+            self.voted_view = view;
+            let vote = self.sign(Vote {
+                view,
+                block: block_digest,
+                replica_id: self.id,
+                signature: Default::default(),
+            });
+            let leader = self.leader(view + 1);
+            if leader == self.id {
+                effects.extend(self.on_vote(vote));
+            } else {
+                effects.push(NodeEffect::Send(
+                    self.replicas[leader as usize],
+                    Message::Vote(vote),
+                ));
+            }
+        }
+        effects.extend(self.propose());
+        effects
+    }
+
+    // the three-chain commit rule
+    fn update(&mut self, digest: Digest) -> Vec<NodeEffect<Message>> {
+        let qc = self.blocks[&digest].justify.clone();
+        self.update_qc_high(qc.clone());
+        let Some(block2) = self.blocks.get(&qc.block) else {
+            return Vec::new();
+        };
+        let digest1 = block2.justify.block;
+        let Some(block1) = self.blocks.get(&digest1) else {
+            return Vec::new();
+        };
+        let (parent2, view1, view0, digest0, parent1) = (
+            block2.parent,
+            block1.view,
+            block1.justify.view,
+            block1.justify.block,
+            block1.parent,
+        );
+        if view1 > self.block_view(&self.locked).unwrap() {
+            self.locked = digest1;
+        }
+        if parent2 == digest1 && parent1 == digest0 {
+            // the block may be missing, so its view is the one of its certificate
+            self.commit(view0, digest0)
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn commit(&mut self, view: u32, digest: Digest) -> Vec<NodeEffect<Message>> {
+        if view <= self.committed.0 {
+            return Vec::new();
+        }
+        self.committed = (view, digest);
+        self.execute()
+    }
+
+    fn execute(&mut self) -> Vec<NodeEffect<Message>> {
+        let executed_view = self.block_view(&self.executed).unwrap();
+        let mut chain = Vec::new();
+        let mut ancestor = self.committed.1;
+        while ancestor != self.executed {
+            let Some(block) = self.blocks.get(&ancestor) else {
+                // missed the proposal, and the block is retained by the others until they commit
+                // the next few ones, so request it again on every commit until it arrives
+                self.missing.insert(ancestor);
+                return vec![NodeEffect::Broadcast(Message::GetBlock(GetBlock {
+                    block: ancestor,
+                    replica_id: self.id,
+                }))];
+            };
+            if block.view <= executed_view {
+                return Vec::new();
+            }
+            chain.push(ancestor);
+            ancestor = block.parent;
+        }
+        self.executed = self.committed.1;
+        self.missing.clear();
+
+        let mut effects = Vec::new();
+        for digest in chain.into_iter().rev() {
+            for request in self.blocks[&digest].requests.clone() {
+                if matches!(self.pending.get(&request.client_id), Some(other) if other.seq <= request.seq)
+                {
+                    self.pending.remove(&request.client_id);
+                }
+                if matches!(self.client_table.get(&request.client_id), Some(reply) if reply.seq >= request.seq)
+                {
+                    continue;
+                }
+                let result = self.app.execute(&request.op);
+                let reply = self.sign(Reply {
+                    seq: request.seq,
+                    replica_id: self.id,
+                    result,
+                    signature: Default::default(),
+                });
+                effects.push(NodeEffect::Send(
+                    request.client_addr,
+                    Message::Reply(reply.clone()),
+                ));
+                self.client_table.insert(request.client_id, reply);
+            }
+        }
+        // blocks that conflict with the committed ones can never be committed
+        self.blocks.retain(|_, block| block.view >= executed_view);
+        self.votes
+            .retain(|digest, _| self.blocks.contains_key(digest));
+        self.new_views = self.new_views.split_off(&self.view);
+        effects
+    }
+
+    fn handle_get_block(&mut self, get_block: GetBlock) -> Vec<NodeEffect<Message>> {
+        let (Some(block), Some(&replica)) = (
+            self.blocks.get(&get_block.block),
+            self.replicas.get(get_block.replica_id as usize),
+        ) else {
+            return Vec::new();
+        };
+        vec![NodeEffect::Send(replica, Message::Block(block.clone()))]
+    }
+
+    fn handle_block(&mut self, block: Block) -> Vec<NodeEffect<Message>> {
+        let block_digest = digest(&block);
+        if !self.missing.remove(&block_digest) {
+            return Vec::new();
+        }
+        self.blocks.insert(block_digest, block);
+        self.execute()
+    }
+
+    fn handle_vote(&mut self, vote: Vote) -> Vec<NodeEffect<Message>> {
+        // a vote for an unknown block is dropped, so faulty replicas cannot make `votes` grow
+        // without bound, and the block is certified by the next leader instead if it is missed
+        if self.leader(vote.view + 1) != self.id
+            || vote.view <= self.qc_high.view
+            || !self.blocks.contains_key(&vote.block)
+        {
+            return Vec::new();
+        }
+        let replica_id = vote.replica_id;
+        let Some(vote) = self.verify(vote, replica_id) else {
+            return Vec::new();
+        };
+        self.on_vote(vote)
+    }
+
+    fn on_vote(&mut self, vote: Vote) -> Vec<NodeEffect<Message>> {
+        let votes = self.votes.entry(vote.block).or_default();
+        votes.insert(vote.replica_id, vote.signature);
+        if votes.len() <= 2 * self.f() {
+            return Vec::new();
+        }
+        let qc = QuorumCert {
+            view: vote.view,
+            block: vote.block,
+            signatures: self
+                .votes
+                .remove(&vote.block)
+                .unwrap()
+                .into_iter()
+                .collect(),
+        };
+        self.update_qc_high(qc);
+        self.votes.retain(
+            |digest, _| !matches!(self.blocks.get(digest), Some(block) if block.view <= vote.view),
+        );
+        self.view = self.view.max(vote.view + 1);
+        self.propose()
+    }
+
+    fn handle_new_view(&mut self, new_view: NewView) -> Vec<NodeEffect<Message>> {
+        if self.leader(new_view.view) != self.id
+            || new_view.view < self.view
+            || new_view.view > self.view + NEW_VIEW_WINDOW
+            || !self.verify_qc(&new_view.qc)
+        {
+            return Vec::new();
+        }
+        let replica_id = new_view.replica_id;
+        let Some(new_view) = self.verify(new_view, replica_id) else {
+            return Vec::new();
+        };
+        self.on_new_view(new_view)
+    }
+
+    fn on_new_view(&mut self, new_view: NewView) -> Vec<NodeEffect<Message>> {
+        self.update_qc_high(new_view.qc);
+        let replicas = self.new_views.entry(new_view.view).or_default();
+        replicas.insert(new_view.replica_id);
+        if replicas.len() > 2 * self.f() {
+            self.view = self.view.max(new_view.view);
+            self.new_views = self.new_views.split_off(&self.view);
+        }
+        self.propose()
+    }
+
+    // the pacemaker
+    fn handle_tick(&mut self) -> Vec<NodeEffect<Message>> {
+        if self.pending.is_empty() {
+            self.ticked = 0;
+            return Vec::new();
+        }
+        self.ticked += 1;
+        if self.ticked < VIEW_TICKS {
+            return Vec::new();
+        }
+        self.ticked = 0;
+        self.view += 1;
+        let new_view = self.sign(NewView {
+            view: self.view,
+            qc: self.qc_high.clone(),
+            replica_id: self.id,
+            signature: Default::default(),
+        });
+        let leader = self.leader(self.view);
+        if leader == self.id {
+            self.on_new_view(new_view)
+        } else {
+            vec![NodeEffect::Send(
+                self.replicas[leader as usize],
+                Message::NewView(new_view),
+            )]
+        }
+    }
+}
+
+impl Protocol<NodeEvent<Message>> for Replica {
+    type Effect = Vec<NodeEffect<Message>>;
+
+    fn update(&mut self, event: NodeEvent<Message>) -> Self::Effect {
+        match event {
+            NodeEvent::Init => Vec::new(),
+            NodeEvent::Tick => self.handle_tick(),
+            NodeEvent::Handle(Message::Request(request)) => self.handle_request(request),
+            NodeEvent::Handle(Message::Proposal(proposal)) => self.handle_proposal(proposal),
+            NodeEvent::Handle(Message::Vote(vote)) => self.handle_vote(vote),
+            NodeEvent::Handle(Message::NewView(new_view)) => self.handle_new_view(new_view),
+            NodeEvent::Handle(Message::GetBlock(get_block)) => self.handle_get_block(get_block),
+            NodeEvent::Handle(Message::Block(block)) => self.handle_block(block),
+            NodeEvent::Handle(Message::Reply(_)) => {
+                UnexpectedMessage("reply").report();
+                Vec::new()
+            }
+            NodeEvent::Timeout(_) => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        crypto::{public_keys, secret_key, sign},
+        simulate::{
+            cluster::{self, assert_results, replica, replica_mut, workload, Cluster},
+            Fault,
+        },
+        NodeAddr::{TestClient, TestReplica},
+        NodeEvent, Protocol,
+    };
+
+    use super::{Client, Message, NewView, Replica, Reply, Vote, NEW_VIEW_WINDOW};
+
+    fn simulate(
+        num_client: u32,
+        num_replica: u32,
+        num_op: usize,
+    ) -> Cluster<Client, Replica, Message> {
+        cluster::new(
+            num_client,
+            num_replica,
+            num_op,
+            |i, _| Client::new(i, TestClient(i), public_keys(num_replica as _)),
+            |i, replicas, app| {
+                Replica::new(
+                    i,
+                    replicas,
+                    secret_key(i),
+                    public_keys(num_replica as _),
+                    app,
+                )
+            },
+        )
+    }
+
+    #[test]
+    fn single_op() {
+        let mut simulate = simulate(1, 4, 1);
+        simulate.init();
+        while simulate.progress() {}
+        assert_results(&simulate, 1, 1);
+        // one view to propose, and three more to commit with the three-chain rule
+        for i in 0..4 {
+            assert_eq!(
+                replica(&simulate, i).block_view(&replica(&simulate, i).executed),
+                Some(1)
+            );
+        }
+    }
+
+    // a reply from a faulty peer is ignored instead of aborting the replica
+    #[test]
+    fn ignore_reply() {
+        let mut simulate = simulate(1, 4, 1);
+        simulate.init();
+        while simulate.progress() {}
+        let reply = Reply {
+            seq: 1,
+            replica_id: 1,
+            result: b"client 0 op 0".to_vec().into(),
+            signature: Default::default(),
+        };
+        // `Replica::update` is the HotStuff procedure, not the `Protocol` one
+        assert!(Protocol::update(
+            replica_mut(&mut simulate, 0),
+            NodeEvent::Handle(Message::Reply(reply))
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn multiple_clients() {
+        let mut simulate = simulate(3, 4, 5);
+        simulate.init();
+        while simulate.progress() {}
+        assert_results(&simulate, 3, 5);
+    }
+
+    // a replica that misses a proposal fetches the block when it is committed
+    #[test]
+    fn block_sync() {
+        let mut simulate = simulate(1, 4, 2);
+        let drop = Fault {
+            drop: 1.,
+            ..Default::default()
+        };
+        simulate.set_link_fault(TestReplica(1), TestReplica(3), drop);
+        simulate.init();
+        // the proposal of view 1 is lost on its way to replica 3, while the rest get through
+        while replica(&simulate, 2).proposed_view < 2 {
+            assert!(simulate.progress());
+        }
+        simulate.set_link_fault(TestReplica(1), TestReplica(3), Fault::default());
+        assert!(simulate.run_until_predicate(
+            |simulate| workload(simulate, 0).results.len() == 2,
+            Duration::from_secs(1)
+        ));
+        while simulate.progress() {}
+        assert_results(&simulate, 1, 2);
+        for i in 0..4 {
+            assert_eq!(replica(&simulate, i).client_table[&0].seq, 2);
+        }
+    }
+
+    // votes and new views that may never be used are not collected
+    #[test]
+    fn far_ahead_messages() {
+        let mut simulate = simulate(1, 4, 1);
+        simulate.init();
+        while simulate.progress() {}
+        let replica = replica_mut(&mut simulate, 0);
+        // replica 0 leads view 44, but the block does not exist
+        let mut vote = Vote {
+            view: 43,
+            block: Default::default(),
+            replica_id: 1,
+            signature: Default::default(),
+        };
+        sign(&mut vote, &secret_key(1));
+        // and it leads this view too, which is too far ahead
+        let mut new_view = NewView {
+            view: (replica.view / 4 + NEW_VIEW_WINDOW) * 4,
+            qc: replica.qc_high.clone(),
+            replica_id: 1,
+            signature: Default::default(),
+        };
+        sign(&mut new_view, &secret_key(1));
+        Protocol::update(&mut *replica, NodeEvent::Handle(Message::Vote(vote)));
+        Protocol::update(&mut *replica, NodeEvent::Handle(Message::NewView(new_view)));
+        assert!(replica.votes.is_empty());
+        assert!(replica.new_views.is_empty());
+    }
+
+    #[test]
+    fn pacemaker() {
+        let mut simulate = simulate(1, 4, 2);
+        simulate.init();
+        // every replica receives the request, and the leader of view 1 proposes
+        for _ in 0..4 {
+            simulate.progress();
+        }
+        // but the proposal is not delivered before every replica times out
+        for _ in 0..10 {
+            for i in 0..4 {
+                simulate.tick(TestReplica(i));
+            }
+        }
+        while simulate.progress() {}
+        assert_results(&simulate, 1, 2);
+        for i in 0..4 {
+            assert!(replica(&simulate, i).view > 2);
+        }
+    }
+}
@@ -1,10 +1,24 @@
 pub mod app;
+pub mod bench;
+pub mod byzantine;
 pub mod crypto;
+pub mod hotstuff;
+pub mod linearizability;
+pub mod local;
+pub mod multipaxos;
 pub mod node;
+pub mod pbft;
 pub mod protocol;
+pub mod raft;
 pub mod simulate;
+pub mod tcp;
+pub mod trace;
 pub mod udp;
+pub mod uds;
 pub mod unreplicated;
+#[cfg(feature = "io-uring")]
+pub mod uring;
+pub mod vr;
 
 pub use crate::app::App;
 pub use crate::node::{NodeAddr, NodeEffect, NodeEvent};
@@ -53,7 +67,38 @@ pub fn capture_interrupt() {
     .unwrap();
     pthread_sigmask(
         SigmaskHow::SIG_BLOCK,
-        Some(&SigSet::from_iter([Signal::SIGINT].into_iter())),
+        Some(&SigSet::from_iter([Signal::SIGINT])),
+        None,
+    )
+    .unwrap();
+}
+
+// the `capture_interrupt` of tests, which stop a single receiving thread with
+// `pthread_kill(thread, SIGUSR1)` instead, so the process-wide interruptions are not counted
+#[cfg(test)]
+pub(crate) fn capture_stop() {
+    use nix::sys::signal::{
+        pthread_sigmask, sigaction, SaFlags, SigAction, SigHandler, SigSet, SigmaskHow, Signal,
+    };
+    use std::ffi::c_int;
+
+    // only to interrupt the blocking call with `EINTR`
+    extern "C" fn handle(_: c_int) {}
+    unsafe {
+        sigaction(
+            Signal::SIGUSR1,
+            &SigAction::new(
+                SigHandler::Handler(handle),
+                SaFlags::empty(),
+                SigSet::empty(),
+            ),
+        )
+    }
+    .unwrap();
+    // delivered only when the thread is blocked with an empty signal mask, as for `SIGINT`
+    pthread_sigmask(
+        SigmaskHow::SIG_BLOCK,
+        Some(&SigSet::from_iter([Signal::SIGUSR1])),
         None,
     )
     .unwrap();
@@ -0,0 +1,93 @@
+use std::{collections::HashSet, time::Instant};
+
+use crate::app::App;
+
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub op: Box<[u8]>,
+    pub result: Box<[u8]>,
+    pub invoke: Instant,
+    pub response: Instant,
+}
+
+// each history contains the records of one client in invocation order, and the index of the
+// history is the client id
+// the ops without response are not recorded, which is fine as long as the checked app cannot
+// observe them, i.e. they are the last op of each client
+pub fn check(app: &mut App, histories: &[&[Record]]) -> bool {
+    let mut next = vec![0; histories.len()];
+    search(app, histories, &mut next, &mut HashSet::new())
+}
+
+// search for a sequential order that is consistent with both real time and the results, by
+// repeatedly choosing the next op to execute among every client's next record
+// (next record indices, app snapshot) => already failed
+fn search(
+    app: &mut App,
+    histories: &[&[Record]],
+    next: &mut Vec<usize>,
+    visited: &mut HashSet<(Vec<usize>, Box<[u8]>)>,
+) -> bool {
+    let Some(min_response) = histories
+        .iter()
+        .zip(&*next)
+        .filter_map(|(history, &i)| history.get(i))
+        .map(|record| record.response)
+        .min()
+    else {
+        return true;
+    };
+    if !visited.insert((next.clone(), app.snapshot())) {
+        return false;
+    }
+    for client_id in 0..histories.len() {
+        let Some(record) = histories[client_id].get(next[client_id]) else {
+            continue;
+        };
+        // some other op has responded before this one is invoked, so that one goes first
+        if record.invoke > min_response {
+            continue;
+        }
+        let snapshot = app.snapshot();
+        if app.execute(&record.op) == record.result {
+            next[client_id] += 1;
+            if search(app, histories, next, visited) {
+                return true;
+            }
+            next[client_id] -= 1;
+        }
+        app.restore(&snapshot);
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::{app, App};
+
+    use super::{check, Record};
+
+    fn record(op: &[u8], result: &[u8], instant: Instant, invoke: u64, response: u64) -> Record {
+        Record {
+            op: op.into(),
+            result: result.into(),
+            invoke: instant + Duration::from_millis(invoke),
+            response: instant + Duration::from_millis(response),
+        }
+    }
+
+    #[test]
+    fn echo() {
+        let instant = Instant::now();
+        let client0 = [
+            record(b"a", b"a", instant, 0, 10),
+            record(b"b", b"b", instant, 20, 30),
+        ];
+        let client1 = [record(b"c", b"c", instant, 5, 25)];
+        assert!(check(&mut App::Echo(app::Echo), &[&client0, &client1]));
+        let client1 = [record(b"c", b"d", instant, 5, 25)];
+        assert!(!check(&mut App::Echo(app::Echo), &[&client0, &client1]));
+    }
+}
@@ -0,0 +1,174 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crossbeam::channel;
+
+use crate::{node::UnhandledTimer, NodeAddr, NodeEffect, NodeEvent, Protocol};
+
+// the routing table of an in-process cluster, which maps the test addresses to the channels that
+// are consumed by the nodes' `Lifecycle`s
+// all nodes should be registered before it is shared with `Tx`s
+pub struct Network<M>(HashMap<NodeAddr, channel::Sender<NodeEvent<M>>>);
+
+impl<M> Default for Network<M> {
+    fn default() -> Self {
+        Self(Default::default())
+    }
+}
+
+impl<M> Network<M> {
+    pub fn register(&mut self, addr: NodeAddr) -> channel::Receiver<NodeEvent<M>> {
+        assert!(matches!(
+            addr,
+            NodeAddr::TestClient(_) | NodeAddr::TestReplica(_)
+        ));
+        let (sender, receiver) = channel::unbounded();
+        assert!(self.0.insert(addr, sender).is_none());
+        receiver
+    }
+}
+
+// deliver messages to other threads without serialization
+// the broadcast group is every replica other than the sender, same as `Simulate`
+pub struct Tx<M> {
+    addr: NodeAddr,
+    network: Arc<Network<M>>,
+}
+
+impl<M> Tx<M> {
+    pub fn new(addr: NodeAddr, network: Arc<Network<M>>) -> Self {
+        Self { addr, network }
+    }
+}
+
+impl<M> Protocol<NodeEffect<M>> for Tx<M>
+where
+    M: Clone,
+{
+    type Effect = ();
+
+    fn update(&mut self, event: NodeEffect<M>) -> Self::Effect {
+        match event {
+            NodeEffect::Send(addr, message) => {
+                // lost if the destination never exists or has stopped, as in `Simulate`
+                if let Some(sender) = self.network.0.get(&addr) {
+                    let _ = sender.send(NodeEvent::Handle(message));
+                }
+            }
+            NodeEffect::Broadcast(message) => {
+                for (&addr, sender) in &self.network.0 {
+                    if matches!(addr, NodeAddr::TestReplica(_)) && addr != self.addr {
+                        let _ = sender.send(NodeEvent::Handle(message.clone()));
+                    }
+                }
+            }
+            // should be intercepted by `node::Timer`
+            NodeEffect::SetTimer(id, _) | NodeEffect::CancelTimer(id) => {
+                UnhandledTimer(id).report()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread::scope,
+    };
+
+    use crate::{
+        app, linearizability,
+        node::{Lifecycle, Workload},
+        protocol::Generate,
+        vr::{Client, Message, Replica},
+        App,
+        NodeAddr::{TestClient, TestReplica},
+        NodeEvent, Protocol,
+    };
+
+    use super::{Network, Tx};
+
+    #[test]
+    fn vr_cluster() {
+        let mut network = Network::<Message>::default();
+        let replicas = (0..3).map(TestReplica).collect::<Box<_>>();
+        let client_channels = (0..3)
+            .map(|i| network.register(TestClient(i)))
+            .collect::<Vec<_>>();
+        let replica_channels = (0..3)
+            .map(|i| network.register(TestReplica(i)))
+            .collect::<Vec<_>>();
+        let network = Arc::new(network);
+
+        let workloads = scope(|s| {
+            let replica_running = replica_channels
+                .into_iter()
+                .enumerate()
+                .map(|(i, channel)| {
+                    let running = Arc::new(AtomicBool::new(false));
+                    let replica = Replica::new(i as _, replicas.clone(), App::Echo(app::Echo));
+                    let tx = Tx::new(TestReplica(i as _), network.clone());
+                    s.spawn({
+                        let running = running.clone();
+                        move || Lifecycle::new(channel, running).deploy(&mut replica.each_then(tx))
+                    });
+                    running
+                })
+                .collect::<Vec<_>>();
+
+            let clients = client_channels
+                .into_iter()
+                .enumerate()
+                .map(|(i, channel)| {
+                    let i = i as u32;
+                    let mut workload = Workload::new_test(
+                        Client::new(i, TestClient(i), replicas.clone()),
+                        (0..10).map(move |j| format!("client {i} op {j}").into_bytes()),
+                    );
+                    let tx = Tx::new(TestClient(i), network.clone());
+                    s.spawn(move || {
+                        let running = Arc::new(AtomicBool::new(false));
+                        Lifecycle::new(channel, running.clone()).deploy(
+                            &mut (|event: NodeEvent<Message>| {
+                                let effect = workload.update(event);
+                                if workload.results.len() == 10 {
+                                    running.store(false, Ordering::SeqCst);
+                                }
+                                effect
+                            })
+                            .each_then(tx),
+                        );
+                        workload
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            let workloads = clients
+                .into_iter()
+                .map(|client| client.join().unwrap())
+                .collect::<Vec<_>>();
+            for running in replica_running {
+                running.store(false, Ordering::SeqCst);
+            }
+            workloads
+        });
+
+        for (i, workload) in workloads.iter().enumerate() {
+            assert_eq!(workload.results.len(), 10);
+            for (j, result) in workload.results.iter().enumerate() {
+                assert_eq!(&**result, format!("client {i} op {j}").as_bytes());
+            }
+        }
+        let histories = workloads
+            .iter()
+            .map(|workload| &workload.history[..])
+            .collect::<Vec<_>>();
+        assert!(linearizability::check(
+            &mut App::Echo(app::Echo),
+            &histories
+        ));
+    }
+}
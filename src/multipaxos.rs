@@ -0,0 +1,636 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app::App,
+    node::{ClientEffect, ClientEvent, ClientState, Request, UnexpectedMessage},
+    NodeAddr, NodeEffect, NodeEvent, Protocol,
+};
+
+// (round, replica id), so ballots of different replicas never tie
+pub type Ballot = (u32, u32);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reply {
+    seq: u32,
+    leader_id: u32,
+    result: Box<[u8]>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Prepare {
+    ballot: Ballot,
+    // the leader already knows the chosen values up to here
+    commit_num: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Promise {
+    ballot: Ballot,
+    // (slot, accepted ballot, value) of accepted slots after the prepare's `commit_num`
+    accepted: Vec<(u32, Ballot, Option<Request>)>,
+    replica_id: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Accept {
+    ballot: Ballot,
+    slot: u32,
+    // `None` for the no-op that fills a gap after leader election
+    request: Option<Request>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Accepted {
+    ballot: Ballot,
+    slot: u32,
+    replica_id: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Commit {
+    ballot: Ballot,
+    commit_num: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sync {
+    commit_num: u32,
+    replica_id: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Learn {
+    // chosen values starting from slot `commit_num + 1`
+    commit_num: u32,
+    values: Vec<Option<Request>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Message {
+    Request(Request),
+    Reply(Reply),
+    Prepare(Prepare),
+    Promise(Promise),
+    Accept(Accept),
+    Accepted(Accepted),
+    Commit(Commit),
+    Sync(Sync),
+    Learn(Learn),
+}
+
+pub struct Client {
+    pub state: ClientState,
+    replicas: Box<[NodeAddr]>,
+    leader_id: u32,
+}
+
+impl Client {
+    pub fn new(id: u32, addr: NodeAddr, replicas: Box<[NodeAddr]>) -> Self {
+        Self {
+            state: ClientState::new(id, addr),
+            replicas,
+            leader_id: 0,
+        }
+    }
+}
+
+impl Protocol<ClientEvent<Message>> for Client {
+    type Effect = Option<ClientEffect<Message>>;
+
+    fn update(&mut self, event: ClientEvent<Message>) -> Self::Effect {
+        match event {
+            ClientEvent::Op(op) => {
+                let request = self.state.invoke(op);
+                Some(ClientEffect::Node(NodeEffect::Send(
+                    self.replicas[self.leader_id as usize],
+                    Message::Request(request),
+                )))
+            }
+            ClientEvent::Node(NodeEvent::Init) => None,
+            ClientEvent::Node(NodeEvent::Tick) => {
+                let request = self.state.tick()?;
+                // the leader may have changed, and only the current one will respond
+                Some(ClientEffect::Node(NodeEffect::Broadcast(Message::Request(
+                    request,
+                ))))
+            }
+            ClientEvent::Node(NodeEvent::Handle(Message::Reply(reply))) => {
+                if !self.state.is_outstanding(reply.seq) {
+                    return None;
+                }
+                self.state.complete();
+                self.leader_id = reply.leader_id;
+                Some(ClientEffect::Result(reply.result))
+            }
+            ClientEvent::Node(NodeEvent::Handle(_)) => None,
+            ClientEvent::Node(NodeEvent::Timeout(_)) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Follower,
+    // running phase 1 with `ballot`
+    Candidate,
+    // running phase 2 with `ballot`
+    Leader,
+}
+
+struct Slot {
+    ballot: Ballot,
+    request: Option<Request>,
+    chosen: bool,
+    // on leader, the acceptors (other than itself) that accepted `ballot`
+    accepted: HashSet<u32>,
+}
+
+pub struct Replica {
+    id: u32,
+    replicas: Box<[NodeAddr]>,
+    app: App,
+    status: Status,
+    // the highest ballot seen so far, which is the one the replica promised as acceptor, and also
+    // the one the replica proposes with when it is not a follower
+    ballot: Ballot,
+    promises: HashMap<u32, Promise>,
+    log: BTreeMap<u32, Slot>,
+    // on leader, the last proposed slot
+    propose_num: u32,
+    // the chosen and executed prefix of the log
+    commit_num: u32,
+    client_table: HashMap<u32, Reply>,
+    // leader: ticks since the last heartbeat
+    // otherwise: ticks since the last message from leader (or since the election started)
+    ticked: u32,
+}
+
+const HEARTBEAT_TICKS: u32 = 2;
+const ELECTION_TICKS: u32 = 10;
+
+impl Replica {
+    pub fn new(id: u32, replicas: Box<[NodeAddr]>, app: App) -> Self {
+        assert!((id as usize) < replicas.len());
+        Self {
+            id,
+            replicas,
+            app,
+            // the initial leader skips phase 1, as nothing can be accepted before its ballot
+            status: if id == 0 {
+                Status::Leader
+            } else {
+                Status::Follower
+            },
+            ballot: (0, 0),
+            promises: Default::default(),
+            log: Default::default(),
+            propose_num: 0,
+            commit_num: 0,
+            client_table: Default::default(),
+            ticked: 0,
+        }
+    }
+
+    fn f(&self) -> usize {
+        (self.replicas.len() - 1) / 2
+    }
+
+    fn send_replica(&self, id: u32, message: Message) -> NodeEffect<Message> {
+        NodeEffect::Send(self.replicas[id as usize], message)
+    }
+
+    // step down if `ballot` is higher, and return whether the message should be accepted
+    fn observe_ballot(&mut self, ballot: Ballot) -> bool {
+        if ballot < self.ballot {
+            return false;
+        }
+        if ballot > self.ballot {
+            self.ballot = ballot;
+            self.status = Status::Follower;
+            self.promises.clear();
+        }
+        true
+    }
+
+    fn start_election(&mut self) -> Vec<NodeEffect<Message>> {
+        self.ballot = (self.ballot.0 + 1, self.id);
+        self.status = Status::Candidate;
+        self.ticked = 0;
+        self.promises.clear();
+        let promise = self.promise();
+        self.promises.insert(self.id, promise);
+        let mut effects = vec![NodeEffect::Broadcast(Message::Prepare(Prepare {
+            ballot: self.ballot,
+            commit_num: self.commit_num,
+        }))];
+        effects.extend(self.check_elected());
+        effects
+    }
+
+    fn promise(&self) -> Promise {
+        Promise {
+            ballot: self.ballot,
+            accepted: self
+                .log
+                .range(self.commit_num + 1..)
+                .map(|(&slot, entry)| (slot, entry.ballot, entry.request.clone()))
+                .collect(),
+            replica_id: self.id,
+        }
+    }
+
+    fn handle_prepare(&mut self, prepare: Prepare) -> Vec<NodeEffect<Message>> {
+        if prepare.ballot <= self.ballot {
+            return Vec::new();
+        }
+        self.observe_ballot(prepare.ballot);
+        self.ticked = 0;
+        let mut promise = self.promise();
+        promise
+            .accepted
+            .retain(|&(slot, _, _)| slot > prepare.commit_num);
+        vec![self.send_replica(prepare.ballot.1, Message::Promise(promise))]
+    }
+
+    fn handle_promise(&mut self, promise: Promise) -> Vec<NodeEffect<Message>> {
+        if self.status != Status::Candidate || promise.ballot != self.ballot {
+            return Vec::new();
+        }
+        self.promises.insert(promise.replica_id, promise);
+        self.check_elected()
+    }
+
+    fn check_elected(&mut self) -> Vec<NodeEffect<Message>> {
+        if self.promises.len() <= self.f() {
+            return Vec::new();
+        }
+        self.status = Status::Leader;
+        self.ticked = 0;
+        let mut merged = BTreeMap::<u32, (Ballot, Option<Request>)>::new();
+        for (slot, ballot, request) in self
+            .promises
+            .drain()
+            .flat_map(|(_, promise)| promise.accepted)
+        {
+            if slot <= self.commit_num {
+                continue;
+            }
+            match merged.get(&slot) {
+                Some((other, _)) if *other >= ballot => {}
+                _ => {
+                    merged.insert(slot, (ballot, request));
+                }
+            }
+        }
+        self.propose_num = merged
+            .keys()
+            .last()
+            .copied()
+            .unwrap_or_default()
+            .max(self.commit_num);
+        let mut effects = Vec::new();
+        for slot in self.commit_num + 1..=self.propose_num {
+            let request = merged.remove(&slot).and_then(|(_, request)| request);
+            effects.extend(self.propose(slot, request));
+        }
+        effects
+    }
+
+    fn propose(&mut self, slot: u32, request: Option<Request>) -> Vec<NodeEffect<Message>> {
+        self.log.insert(
+            slot,
+            Slot {
+                ballot: self.ballot,
+                request: request.clone(),
+                chosen: false,
+                accepted: Default::default(),
+            },
+        );
+        let mut effects = vec![NodeEffect::Broadcast(Message::Accept(Accept {
+            ballot: self.ballot,
+            slot,
+            request,
+        }))];
+        effects.extend(self.check_chosen(slot));
+        effects
+    }
+
+    fn handle_request(&mut self, request: Request) -> Vec<NodeEffect<Message>> {
+        match self.client_table.get(&request.client_id) {
+            Some(reply) if reply.seq > request.seq => return Vec::new(),
+            Some(reply) if reply.seq == request.seq => {
+                return vec![NodeEffect::Send(
+                    request.client_addr,
+                    Message::Reply(reply.clone()),
+                )]
+            }
+            _ => {}
+        }
+        if self.status != Status::Leader {
+            return Vec::new();
+        }
+        let proposed = self.log.range(self.commit_num + 1..).any(|(_, entry)| {
+            matches!(
+                &entry.request,
+                Some(other) if other.client_id == request.client_id && other.seq >= request.seq
+            )
+        });
+        if proposed {
+            return Vec::new();
+        }
+        self.propose_num += 1;
+        self.propose(self.propose_num, Some(request))
+    }
+
+    fn handle_accept(&mut self, accept: Accept) -> Vec<NodeEffect<Message>> {
+        if !self.observe_ballot(accept.ballot) || self.status != Status::Follower {
+            return Vec::new();
+        }
+        self.ticked = 0;
+        match self.log.get(&accept.slot) {
+            Some(entry) if entry.chosen => {}
+            _ => {
+                self.log.insert(
+                    accept.slot,
+                    Slot {
+                        ballot: accept.ballot,
+                        request: accept.request,
+                        chosen: false,
+                        accepted: Default::default(),
+                    },
+                );
+            }
+        }
+        vec![self.send_replica(
+            accept.ballot.1,
+            Message::Accepted(Accepted {
+                ballot: accept.ballot,
+                slot: accept.slot,
+                replica_id: self.id,
+            }),
+        )]
+    }
+
+    fn handle_accepted(&mut self, accepted: Accepted) -> Vec<NodeEffect<Message>> {
+        if self.status != Status::Leader || accepted.ballot != self.ballot {
+            return Vec::new();
+        }
+        let Some(entry) = self.log.get_mut(&accepted.slot) else {
+            return Vec::new();
+        };
+        if entry.ballot != self.ballot {
+            return Vec::new();
+        }
+        entry.accepted.insert(accepted.replica_id);
+        self.check_chosen(accepted.slot)
+    }
+
+    fn check_chosen(&mut self, slot: u32) -> Vec<NodeEffect<Message>> {
+        let f = self.f();
+        let entry = self.log.get_mut(&slot).unwrap();
+        if entry.chosen || entry.accepted.len() < f {
+            return Vec::new();
+        }
+        entry.chosen = true;
+        let commit_num = self.commit_num;
+        let mut effects = self.execute();
+        if self.commit_num != commit_num {
+            effects.push(NodeEffect::Broadcast(Message::Commit(Commit {
+                ballot: self.ballot,
+                commit_num: self.commit_num,
+            })));
+        }
+        effects
+    }
+
+    fn execute(&mut self) -> Vec<NodeEffect<Message>> {
+        let mut effects = Vec::new();
+        while let Some(entry) = self.log.get(&(self.commit_num + 1)) {
+            if !entry.chosen {
+                break;
+            }
+            self.commit_num += 1;
+            let Some(request) = entry.request.clone() else {
+                continue;
+            };
+            // the same request can be proposed twice if the client resent it across elections
+            if matches!(self.client_table.get(&request.client_id), Some(reply) if reply.seq >= request.seq)
+            {
+                continue;
+            }
+            let reply = Reply {
+                seq: request.seq,
+                leader_id: self.ballot.1,
+                result: self.app.execute(&request.op),
+            };
+            if self.status == Status::Leader {
+                effects.push(NodeEffect::Send(
+                    request.client_addr,
+                    Message::Reply(reply.clone()),
+                ));
+            }
+            self.client_table.insert(request.client_id, reply);
+        }
+        effects
+    }
+
+    fn handle_commit(&mut self, commit: Commit) -> Vec<NodeEffect<Message>> {
+        if !self.observe_ballot(commit.ballot) || self.status != Status::Follower {
+            return Vec::new();
+        }
+        self.ticked = 0;
+        // only the values accepted in leader's ballot are known to be the chosen ones
+        for (_, entry) in self.log.range_mut(self.commit_num + 1..=commit.commit_num) {
+            if entry.ballot == commit.ballot {
+                entry.chosen = true;
+            }
+        }
+        let mut effects = self.execute();
+        if self.commit_num < commit.commit_num {
+            effects.push(self.send_replica(
+                commit.ballot.1,
+                Message::Sync(Sync {
+                    commit_num: self.commit_num,
+                    replica_id: self.id,
+                }),
+            ));
+        }
+        effects
+    }
+
+    fn handle_sync(&mut self, sync: Sync) -> Vec<NodeEffect<Message>> {
+        if self.status != Status::Leader || sync.commit_num >= self.commit_num {
+            return Vec::new();
+        }
+        let learn = Learn {
+            commit_num: sync.commit_num,
+            values: self
+                .log
+                .range(sync.commit_num + 1..=self.commit_num)
+                .map(|(_, entry)| entry.request.clone())
+                .collect(),
+        };
+        vec![self.send_replica(sync.replica_id, Message::Learn(learn))]
+    }
+
+    fn handle_learn(&mut self, learn: Learn) -> Vec<NodeEffect<Message>> {
+        for (slot, request) in (learn.commit_num + 1..).zip(learn.values) {
+            if slot <= self.commit_num {
+                continue;
+            }
+            self.log.insert(
+                slot,
+                Slot {
+                    ballot: self.ballot,
+                    request,
+                    chosen: true,
+                    accepted: Default::default(),
+                },
+            );
+        }
+        self.execute()
+    }
+
+    fn handle_tick(&mut self) -> Vec<NodeEffect<Message>> {
+        self.ticked += 1;
+        match self.status {
+            Status::Leader => {
+                if self.ticked < HEARTBEAT_TICKS {
+                    return Vec::new();
+                }
+                self.ticked = 0;
+                // resend the pending proposals in case they are lost
+                let mut effects = self
+                    .log
+                    .range(self.commit_num + 1..)
+                    .filter(|(_, entry)| !entry.chosen)
+                    .map(|(&slot, entry)| {
+                        NodeEffect::Broadcast(Message::Accept(Accept {
+                            ballot: self.ballot,
+                            slot,
+                            request: entry.request.clone(),
+                        }))
+                    })
+                    .collect::<Vec<_>>();
+                effects.push(NodeEffect::Broadcast(Message::Commit(Commit {
+                    ballot: self.ballot,
+                    commit_num: self.commit_num,
+                })));
+                effects
+            }
+            // stagger by id to avoid dueling candidates
+            Status::Follower | Status::Candidate => {
+                if self.ticked < ELECTION_TICKS + 2 * self.id {
+                    return Vec::new();
+                }
+                self.start_election()
+            }
+        }
+    }
+}
+
+impl Protocol<NodeEvent<Message>> for Replica {
+    type Effect = Vec<NodeEffect<Message>>;
+
+    fn update(&mut self, event: NodeEvent<Message>) -> Self::Effect {
+        match event {
+            NodeEvent::Init => Vec::new(),
+            NodeEvent::Tick => self.handle_tick(),
+            NodeEvent::Handle(Message::Request(request)) => self.handle_request(request),
+            NodeEvent::Handle(Message::Prepare(prepare)) => self.handle_prepare(prepare),
+            NodeEvent::Handle(Message::Promise(promise)) => self.handle_promise(promise),
+            NodeEvent::Handle(Message::Accept(accept)) => self.handle_accept(accept),
+            NodeEvent::Handle(Message::Accepted(accepted)) => self.handle_accepted(accepted),
+            NodeEvent::Handle(Message::Commit(commit)) => self.handle_commit(commit),
+            NodeEvent::Handle(Message::Sync(sync)) => self.handle_sync(sync),
+            NodeEvent::Handle(Message::Learn(learn)) => self.handle_learn(learn),
+            NodeEvent::Handle(Message::Reply(_)) => {
+                UnexpectedMessage("reply").report();
+                Vec::new()
+            }
+            NodeEvent::Timeout(_) => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        simulate::cluster::{self, assert_results, replica, replica_mut, Cluster},
+        NodeAddr::{TestClient, TestReplica},
+        NodeEvent, Protocol,
+    };
+
+    use super::{Client, Message, Replica, Reply, Status};
+
+    fn simulate(
+        num_client: u32,
+        num_replica: u32,
+        num_op: usize,
+    ) -> Cluster<Client, Replica, Message> {
+        cluster::new(
+            num_client,
+            num_replica,
+            num_op,
+            |i, replicas| Client::new(i, TestClient(i), replicas),
+            Replica::new,
+        )
+    }
+
+    #[test]
+    fn single_op() {
+        let mut simulate = simulate(1, 3, 1);
+        simulate.init();
+        while simulate.progress() {}
+        assert_results(&simulate, 1, 1);
+    }
+
+    // a reply from a faulty peer is ignored instead of aborting the replica
+    #[test]
+    fn ignore_reply() {
+        let mut simulate = simulate(1, 3, 1);
+        simulate.init();
+        while simulate.progress() {}
+        let reply = Reply {
+            seq: 1,
+            leader_id: 0,
+            result: b"client 0 op 0".to_vec().into(),
+        };
+        assert!(replica_mut(&mut simulate, 0)
+            .update(NodeEvent::Handle(Message::Reply(reply)))
+            .is_empty());
+    }
+
+    #[test]
+    fn pipeline() {
+        let mut simulate = simulate(5, 3, 10);
+        simulate.init();
+        while simulate.progress() {}
+        assert_results(&simulate, 5, 10);
+        for i in 0..3 {
+            assert_eq!(replica(&simulate, i).commit_num, 50);
+        }
+    }
+
+    #[test]
+    fn leader_election() {
+        let mut simulate = simulate(1, 3, 2);
+        // the lowest-id follower times out first
+        for _ in 0..12 {
+            simulate.tick(TestReplica(1));
+        }
+        while simulate.progress() {}
+        assert_eq!(replica(&simulate, 0).status, Status::Follower);
+        assert_eq!(replica(&simulate, 1).status, Status::Leader);
+        // the client sends to the old leader until it is told about the new one
+        simulate.init();
+        while simulate.progress() {}
+        simulate.tick(TestClient(0));
+        simulate.tick(TestClient(0));
+        while simulate.progress() {}
+        assert_results(&simulate, 1, 2);
+        assert_eq!(replica(&simulate, 1).commit_num, 2);
+    }
+}
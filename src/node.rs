@@ -1,7 +1,11 @@
 use std::{
-    net::SocketAddr,
+    collections::HashMap,
+    error::Error,
+    fmt::{self, Display, Formatter},
+    marker::PhantomData,
+    net::{Ipv4Addr, SocketAddr},
     sync::{
-        atomic::{AtomicBool, AtomicU8, Ordering},
+        atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering},
         Arc,
     },
     time::{Duration, Instant},
@@ -11,30 +15,41 @@ use crossbeam::channel;
 use serde::{Deserialize, Serialize};
 
 use crate::{
+    linearizability::Record,
     protocol::{Composite, Generate},
     Protocol,
 };
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum NodeAddr {
     TestClient(u32),
     TestReplica(u32),
     Socket(SocketAddr),
+    // the socket file named by the id, in the directory that the sender is bound in with `uds::bind`
+    // it is not the path itself to keep the address `Copy`
+    Unix(u32),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum NodeEvent<M> {
     Init,
     Handle(M),
     Tick,
+    // the timer set by `NodeEffect::SetTimer` times out, never delivered to the nodes that set no
+    // timer and count ticks instead
+    Timeout(u32),
 }
 
 #[derive(Debug)]
 pub enum NodeEffect<M> {
     Send(NodeAddr, M),
     Broadcast(M),
+    // (re)set the timer with the id, which times out once unless being reset or canceled
+    SetTimer(u32, Duration),
+    CancelTimer(u32),
 }
 
+#[derive(Debug, Serialize, Deserialize)]
 pub enum ClientEvent<M> {
     Op(Box<[u8]>),
     Node(NodeEvent<M>),
@@ -45,16 +60,189 @@ pub enum ClientEffect<M> {
     Node(NodeEffect<M>),
 }
 
+// the request of the replicated protocols, which all identify a client's op by its sequence number
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Request {
+    pub client_id: u32,
+    pub client_addr: NodeAddr,
+    pub seq: u32,
+    pub op: Box<[u8]>,
+}
+
+// the bookkeeping shared by the clients of the replicated protocols, which have at most one
+// outstanding op, and resend it on every tick except the first one after it is invoked
+pub struct ClientState {
+    id: u32,
+    addr: NodeAddr,
+    seq: u32,
+    op: Option<Box<[u8]>>,
+    ticked: u32,
+    pub resend_stats: HashMap<u32, u32>,
+}
+
+impl ClientState {
+    pub fn new(id: u32, addr: NodeAddr) -> Self {
+        assert!(!matches!(addr, NodeAddr::Socket(addr) if addr.ip() == Ipv4Addr::UNSPECIFIED));
+        Self {
+            id,
+            addr,
+            seq: 0,
+            op: None,
+            ticked: 0,
+            resend_stats: Default::default(),
+        }
+    }
+
+    pub fn invoke(&mut self, op: Box<[u8]>) -> Request {
+        assert!(self.op.is_none());
+        self.op = Some(op);
+        self.seq += 1;
+        self.ticked = 0;
+        self.request()
+    }
+
+    // the request to resend, if any
+    pub fn tick(&mut self) -> Option<Request> {
+        self.op.as_ref()?;
+
+        self.ticked += 1;
+        if self.ticked == 1 {
+            return None;
+        }
+
+        if self.ticked == 2 {
+            eprintln!("resend");
+        }
+        *self.resend_stats.entry(self.seq).or_default() += 1;
+        Some(self.request())
+    }
+
+    // whether the reply with `seq` is for the outstanding op
+    pub fn is_outstanding(&self, seq: u32) -> bool {
+        self.op.is_some() && seq == self.seq
+    }
+
+    pub fn complete(&mut self) {
+        assert!(self.op.take().is_some());
+    }
+
+    fn request(&self) -> Request {
+        Request {
+            client_id: self.id,
+            client_addr: self.addr,
+            seq: self.seq,
+            op: self.op.clone().unwrap(),
+        }
+    }
+}
+
+// also the tick interval of `Simulate` virtual time
+pub const TICK_INTERVAL: Duration = Duration::from_millis(10);
+
 pub struct Lifecycle<M> {
     message_channel: channel::Receiver<NodeEvent<M>>,
     running: Arc<AtomicBool>,
+    // (timer id, deadline or `None` if canceled)
+    timer_sender: channel::Sender<(u32, Option<Instant>)>,
+    timer_receiver: channel::Receiver<(u32, Option<Instant>)>,
+    timers: HashMap<u32, Instant>,
 }
 
 impl<M> Lifecycle<M> {
     pub fn new(message_channel: channel::Receiver<NodeEvent<M>>, running: Arc<AtomicBool>) -> Self {
+        let (timer_sender, timer_receiver) = channel::unbounded();
         Self {
             message_channel,
             running,
+            timer_sender,
+            timer_receiver,
+            timers: Default::default(),
+        }
+    }
+
+    // the effect stage that must be placed in front of the nodes' effects if they use timers
+    pub fn timer(&self) -> Timer<M> {
+        Timer(self.timer_sender.clone(), PhantomData)
+    }
+
+    fn update_timers(&mut self) {
+        for (id, deadline) in self.timer_receiver.try_iter() {
+            if let Some(deadline) = deadline {
+                self.timers.insert(id, deadline);
+            } else {
+                self.timers.remove(&id);
+            }
+        }
+    }
+}
+
+// intercept timer effects for `Lifecycle`, and pass through the others
+// it works because the stage runs synchronously on the `Lifecycle` thread, so the timers are
+// updated before the next event is delivered
+pub struct Timer<M>(channel::Sender<(u32, Option<Instant>)>, PhantomData<M>);
+
+impl<M> Protocol<NodeEffect<M>> for Timer<M> {
+    type Effect = Option<NodeEffect<M>>;
+
+    fn update(&mut self, event: NodeEffect<M>) -> Self::Effect {
+        match event {
+            NodeEffect::SetTimer(id, duration) => {
+                self.0.send((id, Some(Instant::now() + duration))).unwrap();
+                None
+            }
+            NodeEffect::CancelTimer(id) => {
+                self.0.send((id, None)).unwrap();
+                None
+            }
+            effect => Some(effect),
+        }
+    }
+}
+
+// a timer effect that reaches a transport, i.e. no `Timer` is deployed in front of it, so the
+// timer never times out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnhandledTimer(pub u32);
+
+impl Display for UnhandledTimer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "timer {} is not handled by `node::Timer`", self.0)
+    }
+}
+
+impl Error for UnhandledTimer {}
+
+impl UnhandledTimer {
+    // the transports drop the effect and report with this, which logs less as more are reported,
+    // in case every op sets a timer
+    pub fn report(self) {
+        static COUNT: AtomicU32 = AtomicU32::new(0);
+        let count = COUNT.fetch_add(1, Ordering::Relaxed) + 1;
+        if count.is_power_of_two() {
+            eprintln!("dropped {count} timer effect(s): {self}");
+        }
+    }
+}
+
+// a well-formed message that the receiving node never expects, e.g. a reply sent to a replica,
+// which may come from a faulty or malicious peer so it must not abort the node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnexpectedMessage(pub &'static str);
+
+impl Display for UnexpectedMessage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} message is not expected", self.0)
+    }
+}
+
+impl UnexpectedMessage {
+    // the node ignores the message and reports with this, which logs less as more are reported, in
+    // case of flooding
+    pub fn report(self) {
+        static COUNT: AtomicU32 = AtomicU32::new(0);
+        let count = COUNT.fetch_add(1, Ordering::Relaxed) + 1;
+        if count.is_power_of_two() {
+            eprintln!("ignored {count} message(s): {self}");
         }
     }
 }
@@ -68,17 +256,45 @@ impl<M> Generate for Lifecycle<M> {
     {
         assert!(!self.running.swap(true, Ordering::SeqCst));
         node.update(NodeEvent::Init);
+        self.update_timers();
 
-        let tick_interval = Duration::from_millis(10);
-        let mut deadline = Instant::now() + tick_interval;
+        let mut tick_deadline = Instant::now() + TICK_INTERVAL;
         while self.running.load(Ordering::SeqCst) {
-            if Instant::now() >= deadline {
-                deadline = Instant::now() + tick_interval;
+            let now = Instant::now();
+            if now >= tick_deadline {
+                tick_deadline = now + TICK_INTERVAL;
                 node.update(NodeEvent::Tick);
+                self.update_timers();
+            }
+            let mut timeouts = self
+                .timers
+                .iter()
+                .filter(|(_, &deadline)| deadline <= now)
+                .map(|(&id, &deadline)| (deadline, id))
+                .collect::<Vec<_>>();
+            timeouts.sort_unstable();
+            for (_, id) in timeouts {
+                // skip if reset or canceled by a previous timeout
+                if self
+                    .timers
+                    .get(&id)
+                    .is_some_and(|&deadline| deadline <= now)
+                {
+                    self.timers.remove(&id);
+                    node.update(NodeEvent::Timeout(id));
+                    self.update_timers();
+                }
             }
+
+            let deadline = self
+                .timers
+                .values()
+                .copied()
+                .fold(tick_deadline, Instant::min);
             match self.message_channel.recv_deadline(deadline) {
                 Ok(event) => {
                     node.update(event);
+                    self.update_timers();
                 }
                 Err(channel::RecvTimeoutError::Disconnected) => break,
                 Err(channel::RecvTimeoutError::Timeout) => {}
@@ -91,6 +307,11 @@ pub struct Workload<N, I> {
     pub node: N,
     ops: I,
     pub results: Vec<Box<[u8]>>,
+    // recorded in every mode of test workloads, for checking linearizability
+    pub history: Vec<Record>,
+    record: bool,
+    // the outstanding op if recording, invoked at `instant`
+    op: Option<Box<[u8]>>,
     instant: Instant,
     pub latencies: Vec<Duration>,
     pub mode: Arc<AtomicU8>,
@@ -114,17 +335,24 @@ impl<N, I> Workload<N, I> {
             node,
             ops,
             results: Default::default(),
+            history: Default::default(),
+            record: true,
+            op: None,
             instant: Instant::now(),
             latencies: Default::default(),
             mode: Arc::new(AtomicU8::new(WorkloadMode::Test as _)),
         }
     }
 
+    // no history is recorded, which would clone every op on the measured path and grow unbounded
     pub fn new_benchmark(node: N, ops: I, mode: Arc<AtomicU8>) -> Self {
         Self {
             node,
             ops,
             results: Default::default(),
+            history: Default::default(),
+            record: false,
+            op: None,
             instant: Instant::now(),
             latencies: Default::default(),
             mode,
@@ -139,8 +367,12 @@ impl<N, I> Workload<N, I> {
         O: Into<Box<[u8]>>,
     {
         if let Some(op) = self.ops.next() {
+            let op = op.into();
+            if self.record {
+                self.op = Some(op.clone());
+            }
             self.instant = Instant::now();
-            self.node.update(ClientEvent::Op(op.into())).map(|effect| {
+            self.node.update(ClientEvent::Op(op)).map(|effect| {
                 if let ClientEffect::Node(effect) = effect {
                     Vec::<_>::pure(effect)
                 } else {
@@ -161,6 +393,15 @@ impl<N, I> Workload<N, I> {
     {
         match effect {
             ClientEffect::Result(result) => {
+                // a result without matching invocation is not a part of the history
+                if let Some(op) = self.op.take() {
+                    self.history.push(Record {
+                        op,
+                        result: result.clone(),
+                        invoke: self.instant,
+                        response: Instant::now(),
+                    })
+                }
                 match self.mode.load(Ordering::SeqCst) {
                     WorkloadMode::DISCARD => {}
                     WorkloadMode::TEST => self.results.push(result),
@@ -0,0 +1,1239 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use secp256k1::{PublicKey, SecretKey};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app::App,
+    crypto::{digest, sign, verify, CryptoMessage, Digest, Signature},
+    node::{ClientEffect, ClientEvent, ClientState, Request, UnexpectedMessage},
+    NodeAddr, NodeEffect, NodeEvent, Protocol,
+};
+
+// client authentication is out of scope, so requests are not signed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reply {
+    view: u32,
+    seq: u32,
+    replica_id: u32,
+    result: Box<[u8]>,
+    signature: Signature,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrePrepare {
+    view: u32,
+    op_num: u32,
+    // `None` for the null request that fills a gap after view change
+    request: Option<Request>,
+    signature: Signature,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Prepare {
+    view: u32,
+    op_num: u32,
+    digest: Digest,
+    replica_id: u32,
+    signature: Signature,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Commit {
+    view: u32,
+    op_num: u32,
+    digest: Digest,
+    replica_id: u32,
+    signature: Signature,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    op_num: u32,
+    digest: Digest,
+    replica_id: u32,
+    signature: Signature,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PreparedProof {
+    pre_prepare: PrePrepare,
+    prepares: Vec<Prepare>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ViewChange {
+    view: u32,
+    checkpoint_op_num: u32,
+    checkpoint_proof: Vec<Checkpoint>,
+    prepared: Vec<PreparedProof>,
+    replica_id: u32,
+    signature: Signature,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewView {
+    view: u32,
+    view_changes: Vec<ViewChange>,
+    pre_prepares: Vec<PrePrepare>,
+    signature: Signature,
+}
+
+macro_rules! impl_crypto_message {
+    ($($message:ident),*) => {
+        $(
+            impl CryptoMessage for $message {
+                fn signature(&mut self) -> Option<&mut Signature> {
+                    Some(&mut self.signature)
+                }
+            }
+        )*
+    };
+}
+
+impl_crypto_message!(Reply, PrePrepare, Prepare, Commit, Checkpoint, ViewChange, NewView);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Message {
+    Request(Request),
+    Reply(Reply),
+    PrePrepare(PrePrepare),
+    Prepare(Prepare),
+    Commit(Commit),
+    Checkpoint(Checkpoint),
+    ViewChange(ViewChange),
+    NewView(NewView),
+}
+
+fn f(num_replica: usize) -> usize {
+    (num_replica - 1) / 3
+}
+
+pub struct Client {
+    pub state: ClientState,
+    replicas: Box<[NodeAddr]>,
+    public_keys: Box<[PublicKey]>,
+    view: u32,
+    replies: HashMap<u32, Reply>,
+}
+
+impl Client {
+    pub fn new(
+        id: u32,
+        addr: NodeAddr,
+        replicas: Box<[NodeAddr]>,
+        public_keys: Box<[PublicKey]>,
+    ) -> Self {
+        assert_eq!(replicas.len(), public_keys.len());
+        Self {
+            state: ClientState::new(id, addr),
+            replicas,
+            public_keys,
+            view: 0,
+            replies: Default::default(),
+        }
+    }
+}
+
+impl Protocol<ClientEvent<Message>> for Client {
+    type Effect = Option<ClientEffect<Message>>;
+
+    fn update(&mut self, event: ClientEvent<Message>) -> Self::Effect {
+        match event {
+            ClientEvent::Op(op) => {
+                let request = self.state.invoke(op);
+                self.replies.clear();
+                let primary = self.replicas[self.view as usize % self.replicas.len()];
+                Some(ClientEffect::Node(NodeEffect::Send(
+                    primary,
+                    Message::Request(request),
+                )))
+            }
+            ClientEvent::Node(NodeEvent::Init) => None,
+            ClientEvent::Node(NodeEvent::Tick) => {
+                let request = self.state.tick()?;
+                // backups forward the request to primary and start suspecting it
+                Some(ClientEffect::Node(NodeEffect::Broadcast(Message::Request(
+                    request,
+                ))))
+            }
+            ClientEvent::Node(NodeEvent::Handle(Message::Reply(reply))) => {
+                if !self.state.is_outstanding(reply.seq) {
+                    return None;
+                }
+                let public_key = self.public_keys.get(reply.replica_id as usize)?;
+                let reply = verify(reply, public_key)?;
+                self.replies.insert(reply.replica_id, reply.clone());
+                let num_match = self
+                    .replies
+                    .values()
+                    .filter(|other| other.result == reply.result)
+                    .count();
+                if num_match <= f(self.replicas.len()) {
+                    return None;
+                }
+                self.state.complete();
+                self.view = self.view.max(reply.view);
+                Some(ClientEffect::Result(reply.result))
+            }
+            ClientEvent::Node(NodeEvent::Handle(_)) => None,
+            ClientEvent::Node(NodeEvent::Timeout(_)) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Normal,
+    ViewChange,
+}
+
+#[derive(Default)]
+struct Entry {
+    pre_prepare: Option<PrePrepare>,
+    digest: Digest,
+    // the latest prepare/commit from each replica, which may be of a view later than the
+    // pre-prepare one if it arrives before the new view is entered
+    prepares: HashMap<u32, Prepare>,
+    commits: HashMap<u32, Commit>,
+}
+
+// there is no state transfer, so a replica that misses the ops before a stable checkpoint of the
+// others, e.g. partitioned for longer than `LOG_WINDOW` ops, never executes again, and counts as
+// one of the `f` faulty replicas from then on
+pub struct Replica {
+    id: u32,
+    replicas: Box<[NodeAddr]>,
+    secret_key: SecretKey,
+    public_keys: Box<[PublicKey]>,
+    app: App,
+    status: Status,
+    view: u32,
+    // on primary, the last assigned op number
+    op_num: u32,
+    execute_num: u32,
+    log: BTreeMap<u32, Entry>,
+    // digest of the execution history, which is agreed on by checkpoints
+    history: Digest,
+    checkpoints: BTreeMap<u32, HashMap<u32, Checkpoint>>,
+    // the low watermark
+    checkpoint_op_num: u32,
+    checkpoint_proof: Vec<Checkpoint>,
+    client_table: HashMap<u32, Reply>,
+    // on backups, the requests that are received from clients but not executed yet
+    waiting: HashMap<u32, u32>,
+    view_changes: BTreeMap<u32, HashMap<u32, ViewChange>>,
+    // the view that was last entered, view changes after it are consecutive failed attempts
+    normal_view: u32,
+    ticked: u32,
+}
+
+const CHECKPOINT_INTERVAL: u32 = 50;
+// the high watermark is the low one plus this
+const LOG_WINDOW: u32 = 2 * CHECKPOINT_INTERVAL;
+const VIEW_CHANGE_TICKS: u32 = 10;
+const MAX_BACKOFF_SHIFT: u32 = 6;
+// view changes are only collected for this many views after the current one, so faulty replicas
+// cannot make `view_changes` grow without bound
+const VIEW_CHANGE_WINDOW: u32 = 10;
+
+impl Replica {
+    pub fn new(
+        id: u32,
+        replicas: Box<[NodeAddr]>,
+        secret_key: SecretKey,
+        public_keys: Box<[PublicKey]>,
+        app: App,
+    ) -> Self {
+        assert!((id as usize) < replicas.len());
+        assert_eq!(replicas.len(), public_keys.len());
+        Self {
+            id,
+            replicas,
+            secret_key,
+            public_keys,
+            app,
+            status: Status::Normal,
+            view: 0,
+            op_num: 0,
+            execute_num: 0,
+            log: Default::default(),
+            history: Default::default(),
+            checkpoints: Default::default(),
+            checkpoint_op_num: 0,
+            checkpoint_proof: Default::default(),
+            client_table: Default::default(),
+            waiting: Default::default(),
+            view_changes: Default::default(),
+            normal_view: 0,
+            ticked: 0,
+        }
+    }
+
+    fn f(&self) -> usize {
+        f(self.replicas.len())
+    }
+
+    fn primary(&self, view: u32) -> u32 {
+        view % self.replicas.len() as u32
+    }
+
+    fn is_primary(&self) -> bool {
+        self.primary(self.view) == self.id
+    }
+
+    fn in_window(&self, op_num: u32) -> bool {
+        op_num > self.checkpoint_op_num && op_num <= self.checkpoint_op_num + LOG_WINDOW
+    }
+
+    fn verify<M>(&self, message: M, replica_id: u32) -> Option<M>
+    where
+        M: CryptoMessage,
+    {
+        verify(message, self.public_keys.get(replica_id as usize)?)
+    }
+
+    fn sign<M>(&self, mut message: M) -> M
+    where
+        M: CryptoMessage,
+    {
+        sign(&mut message, &self.secret_key);
+        message
+    }
+
+    fn handle_request(&mut self, request: Request) -> Vec<NodeEffect<Message>> {
+        match self.client_table.get(&request.client_id) {
+            Some(reply) if reply.seq > request.seq => return Vec::new(),
+            Some(reply) if reply.seq == request.seq => {
+                return vec![NodeEffect::Send(
+                    request.client_addr,
+                    Message::Reply(reply.clone()),
+                )]
+            }
+            _ => {}
+        }
+        if self.status != Status::Normal {
+            return Vec::new();
+        }
+        if !self.is_primary() {
+            if self.waiting.is_empty() {
+                self.ticked = 0;
+            }
+            self.waiting.insert(request.client_id, request.seq);
+            let primary = self.replicas[self.primary(self.view) as usize];
+            return vec![NodeEffect::Send(primary, Message::Request(request))];
+        }
+
+        // the request is either ordered already, or dropped and resent later
+        let ordered = self.log.range(self.execute_num + 1..).any(|(_, entry)| {
+            matches!(
+                &entry.pre_prepare,
+                Some(PrePrepare { view, request: Some(other), .. })
+                    if *view == self.view
+                        && other.client_id == request.client_id
+                        && other.seq >= request.seq
+            )
+        });
+        if ordered || !self.in_window(self.op_num + 1) {
+            return Vec::new();
+        }
+        self.op_num += 1;
+        let pre_prepare = self.sign(PrePrepare {
+            view: self.view,
+            op_num: self.op_num,
+            request: Some(request),
+            signature: Default::default(),
+        });
+        self.insert_pre_prepare(pre_prepare.clone());
+        vec![NodeEffect::Broadcast(Message::PrePrepare(pre_prepare))]
+    }
+
+    fn insert_pre_prepare(&mut self, pre_prepare: PrePrepare) {
+        let entry = self.log.entry(pre_prepare.op_num).or_default();
+        entry.digest = digest(&pre_prepare.request);
+        entry.pre_prepare = Some(pre_prepare);
+    }
+
+    // send and accept the replica's own prepare
+    fn prepare(&mut self, op_num: u32) -> Vec<NodeEffect<Message>> {
+        let prepare = self.sign(Prepare {
+            view: self.view,
+            op_num,
+            digest: self.log[&op_num].digest,
+            replica_id: self.id,
+            signature: Default::default(),
+        });
+        self.log
+            .get_mut(&op_num)
+            .unwrap()
+            .prepares
+            .insert(self.id, prepare.clone());
+        let mut effects = vec![NodeEffect::Broadcast(Message::Prepare(prepare))];
+        effects.extend(self.check_prepared(op_num));
+        effects
+    }
+
+    fn handle_pre_prepare(&mut self, pre_prepare: PrePrepare) -> Vec<NodeEffect<Message>> {
+        if self.status != Status::Normal
+            || pre_prepare.view != self.view
+            || self.is_primary()
+            || !self.in_window(pre_prepare.op_num)
+        {
+            return Vec::new();
+        }
+        let Some(pre_prepare) = self.verify(pre_prepare, self.primary(self.view)) else {
+            return Vec::new();
+        };
+        if let Some(Entry {
+            pre_prepare: Some(other),
+            ..
+        }) = self.log.get(&pre_prepare.op_num)
+        {
+            // either a duplication or an equivocation
+            if other.view == self.view {
+                return Vec::new();
+            }
+        }
+        self.insert_pre_prepare(pre_prepare.clone());
+        self.prepare(pre_prepare.op_num)
+    }
+
+    fn handle_prepare(&mut self, prepare: Prepare) -> Vec<NodeEffect<Message>> {
+        if prepare.view < self.view
+            || !self.in_window(prepare.op_num)
+            || prepare.replica_id == self.primary(prepare.view)
+        {
+            return Vec::new();
+        }
+        let replica_id = prepare.replica_id;
+        let Some(prepare) = self.verify(prepare, replica_id) else {
+            return Vec::new();
+        };
+        let op_num = prepare.op_num;
+        let entry = self.log.entry(op_num).or_default();
+        match entry.prepares.get(&prepare.replica_id) {
+            Some(other) if other.view >= prepare.view => {}
+            _ => {
+                entry.prepares.insert(prepare.replica_id, prepare);
+            }
+        }
+        self.check_prepared(op_num)
+    }
+
+    fn is_prepared(&self, entry: &Entry) -> bool {
+        let Some(pre_prepare) = &entry.pre_prepare else {
+            return false;
+        };
+        entry
+            .prepares
+            .values()
+            .filter(|prepare| prepare.view == pre_prepare.view && prepare.digest == entry.digest)
+            .count()
+            >= 2 * self.f()
+    }
+
+    fn check_prepared(&mut self, op_num: u32) -> Vec<NodeEffect<Message>> {
+        let entry = &self.log[&op_num];
+        if self.status != Status::Normal
+            || !self.is_prepared(entry)
+            || entry.pre_prepare.as_ref().unwrap().view != self.view
+            || matches!(entry.commits.get(&self.id), Some(commit) if commit.view == self.view)
+        {
+            return self.execute();
+        }
+        let commit = self.sign(Commit {
+            view: self.view,
+            op_num,
+            digest: entry.digest,
+            replica_id: self.id,
+            signature: Default::default(),
+        });
+        self.log
+            .get_mut(&op_num)
+            .unwrap()
+            .commits
+            .insert(self.id, commit.clone());
+        let mut effects = vec![NodeEffect::Broadcast(Message::Commit(commit))];
+        effects.extend(self.execute());
+        effects
+    }
+
+    fn handle_commit(&mut self, commit: Commit) -> Vec<NodeEffect<Message>> {
+        if commit.view < self.view || !self.in_window(commit.op_num) {
+            return Vec::new();
+        }
+        let replica_id = commit.replica_id;
+        let Some(commit) = self.verify(commit, replica_id) else {
+            return Vec::new();
+        };
+        let entry = self.log.entry(commit.op_num).or_default();
+        match entry.commits.get(&commit.replica_id) {
+            Some(other) if other.view >= commit.view => {}
+            _ => {
+                entry.commits.insert(commit.replica_id, commit);
+            }
+        }
+        self.execute()
+    }
+
+    fn is_committed(&self, entry: &Entry) -> bool {
+        let Some(pre_prepare) = &entry.pre_prepare else {
+            return false;
+        };
+        self.is_prepared(entry)
+            && entry
+                .commits
+                .values()
+                .filter(|commit| commit.view == pre_prepare.view && commit.digest == entry.digest)
+                .count()
+                > 2 * self.f()
+    }
+
+    fn execute(&mut self) -> Vec<NodeEffect<Message>> {
+        let mut effects = Vec::new();
+        while self.status == Status::Normal {
+            let Some(entry) = self.log.get(&(self.execute_num + 1)) else {
+                break;
+            };
+            if !self.is_committed(entry) {
+                break;
+            }
+            self.execute_num += 1;
+            self.ticked = 0;
+            let request = entry.pre_prepare.as_ref().unwrap().request.clone();
+            let result = if let Some(request) = &request {
+                self.execute_request(request, &mut effects)
+            } else {
+                Default::default()
+            };
+            self.history = digest(&(self.history, self.execute_num, request, result));
+            if self.execute_num.is_multiple_of(CHECKPOINT_INTERVAL) {
+                let checkpoint = self.sign(Checkpoint {
+                    op_num: self.execute_num,
+                    digest: self.history,
+                    replica_id: self.id,
+                    signature: Default::default(),
+                });
+                effects.push(NodeEffect::Broadcast(Message::Checkpoint(
+                    checkpoint.clone(),
+                )));
+                self.insert_checkpoint(checkpoint);
+            }
+        }
+        effects
+    }
+
+    fn execute_request(
+        &mut self,
+        request: &Request,
+        effects: &mut Vec<NodeEffect<Message>>,
+    ) -> Box<[u8]> {
+        if matches!(self.waiting.get(&request.client_id), Some(&seq) if seq <= request.seq) {
+            self.waiting.remove(&request.client_id);
+        }
+        // the same request can be ordered twice if the client resent it across a view change
+        if matches!(self.client_table.get(&request.client_id), Some(reply) if reply.seq >= request.seq)
+        {
+            return Default::default();
+        }
+        let result = self.app.execute(&request.op);
+        let reply = self.sign(Reply {
+            view: self.view,
+            seq: request.seq,
+            replica_id: self.id,
+            result,
+            signature: Default::default(),
+        });
+        effects.push(NodeEffect::Send(
+            request.client_addr,
+            Message::Reply(reply.clone()),
+        ));
+        let result = reply.result.clone();
+        self.client_table.insert(request.client_id, reply);
+        result
+    }
+
+    fn handle_checkpoint(&mut self, checkpoint: Checkpoint) -> Vec<NodeEffect<Message>> {
+        // the replica cannot execute beyond the high watermark to take its own checkpoint
+        if !self.in_window(checkpoint.op_num)
+            || !checkpoint.op_num.is_multiple_of(CHECKPOINT_INTERVAL)
+        {
+            return Vec::new();
+        }
+        let replica_id = checkpoint.replica_id;
+        if let Some(checkpoint) = self.verify(checkpoint, replica_id) {
+            self.insert_checkpoint(checkpoint)
+        }
+        Vec::new()
+    }
+
+    fn insert_checkpoint(&mut self, checkpoint: Checkpoint) {
+        let op_num = checkpoint.op_num;
+        self.checkpoints
+            .entry(op_num)
+            .or_default()
+            .insert(checkpoint.replica_id, checkpoint);
+        // state transfer is not implemented, so a replica only collects garbage up to the point
+        // it has executed itself
+        let Some(own) = self.checkpoints[&op_num].get(&self.id) else {
+            return;
+        };
+        let proof = self.checkpoints[&op_num]
+            .values()
+            .filter(|checkpoint| checkpoint.digest == own.digest)
+            .cloned()
+            .collect::<Vec<_>>();
+        if proof.len() <= 2 * self.f() {
+            return;
+        }
+        self.checkpoint_op_num = op_num;
+        self.checkpoint_proof = proof;
+        self.log = self.log.split_off(&(op_num + 1));
+        self.checkpoints = self.checkpoints.split_off(&(op_num + 1));
+    }
+
+    fn prepared_proofs(&self) -> Vec<PreparedProof> {
+        self.log
+            .range(self.checkpoint_op_num + 1..)
+            .filter(|(_, entry)| self.is_prepared(entry))
+            .map(|(_, entry)| {
+                let pre_prepare = entry.pre_prepare.clone().unwrap();
+                let prepares = entry
+                    .prepares
+                    .values()
+                    .filter(|prepare| {
+                        prepare.view == pre_prepare.view && prepare.digest == entry.digest
+                    })
+                    .cloned()
+                    .collect();
+                PreparedProof {
+                    pre_prepare,
+                    prepares,
+                }
+            })
+            .collect()
+    }
+
+    fn start_view_change(&mut self, view: u32) -> Vec<NodeEffect<Message>> {
+        self.view = view;
+        self.status = Status::ViewChange;
+        self.ticked = 0;
+        self.view_changes = self.view_changes.split_off(&view);
+        let view_change = self.sign(ViewChange {
+            view,
+            checkpoint_op_num: self.checkpoint_op_num,
+            checkpoint_proof: self.checkpoint_proof.clone(),
+            prepared: self.prepared_proofs(),
+            replica_id: self.id,
+            signature: Default::default(),
+        });
+        self.view_changes
+            .entry(view)
+            .or_default()
+            .insert(self.id, view_change.clone());
+        let mut effects = vec![NodeEffect::Broadcast(Message::ViewChange(view_change))];
+        effects.extend(self.check_new_view());
+        effects
+    }
+
+    fn verify_view_change(&self, view_change: ViewChange) -> Option<ViewChange> {
+        let replica_id = view_change.replica_id;
+        let view_change = self.verify(view_change, replica_id)?;
+        if view_change.checkpoint_op_num != 0 {
+            let mut replicas = HashSet::new();
+            for checkpoint in &view_change.checkpoint_proof {
+                if checkpoint.op_num != view_change.checkpoint_op_num
+                    || checkpoint.digest != view_change.checkpoint_proof[0].digest
+                    || !replicas.insert(checkpoint.replica_id)
+                {
+                    return None;
+                }
+                self.verify(checkpoint.clone(), checkpoint.replica_id)?;
+            }
+            if replicas.len() <= 2 * self.f() {
+                return None;
+            }
+        }
+        for proof in &view_change.prepared {
+            let pre_prepare = &proof.pre_prepare;
+            if pre_prepare.view >= view_change.view
+                || pre_prepare.op_num <= view_change.checkpoint_op_num
+            {
+                return None;
+            }
+            self.verify(pre_prepare.clone(), self.primary(pre_prepare.view))?;
+            let digest = digest(&pre_prepare.request);
+            let mut replicas = HashSet::new();
+            for prepare in &proof.prepares {
+                if prepare.view != pre_prepare.view
+                    || prepare.op_num != pre_prepare.op_num
+                    || prepare.digest != digest
+                    || prepare.replica_id == self.primary(pre_prepare.view)
+                    || !replicas.insert(prepare.replica_id)
+                {
+                    return None;
+                }
+                self.verify(prepare.clone(), prepare.replica_id)?;
+            }
+            if replicas.len() < 2 * self.f() {
+                return None;
+            }
+        }
+        Some(view_change)
+    }
+
+    fn handle_view_change(&mut self, view_change: ViewChange) -> Vec<NodeEffect<Message>> {
+        if view_change.view < self.view
+            || (view_change.view == self.view && self.status == Status::Normal)
+            || view_change.view > self.view + VIEW_CHANGE_WINDOW
+        {
+            return Vec::new();
+        }
+        let Some(view_change) = self.verify_view_change(view_change) else {
+            return Vec::new();
+        };
+        let view = view_change.view;
+        let view_changes = self.view_changes.entry(view).or_default();
+        view_changes.insert(view_change.replica_id, view_change);
+        // join a view change as soon as it cannot be started by faulty replicas only
+        if view > self.view && view_changes.len() > self.f() {
+            return self.start_view_change(view);
+        }
+        self.check_new_view()
+    }
+
+    // the pre-prepares a new view starts with, which are determined by the view changes
+    fn new_view_requests(view_changes: &[ViewChange]) -> (u32, Vec<(u32, Option<Request>)>) {
+        let checkpoint_op_num = view_changes
+            .iter()
+            .map(|view_change| view_change.checkpoint_op_num)
+            .max()
+            .unwrap();
+        let mut prepared = BTreeMap::<u32, &PrePrepare>::new();
+        for proof in view_changes
+            .iter()
+            .flat_map(|view_change| &view_change.prepared)
+        {
+            let pre_prepare = &proof.pre_prepare;
+            if pre_prepare.op_num <= checkpoint_op_num {
+                continue;
+            }
+            match prepared.get(&pre_prepare.op_num) {
+                Some(other) if other.view >= pre_prepare.view => {}
+                _ => {
+                    prepared.insert(pre_prepare.op_num, pre_prepare);
+                }
+            }
+        }
+        let max_op_num = prepared.keys().last().copied().unwrap_or(checkpoint_op_num);
+        let requests = (checkpoint_op_num + 1..=max_op_num)
+            .map(|op_num| {
+                let request = prepared
+                    .get(&op_num)
+                    .and_then(|pre_prepare| pre_prepare.request.clone());
+                (op_num, request)
+            })
+            .collect();
+        (checkpoint_op_num, requests)
+    }
+
+    fn check_new_view(&mut self) -> Vec<NodeEffect<Message>> {
+        if self.status != Status::ViewChange || !self.is_primary() {
+            return Vec::new();
+        }
+        let Some(view_changes) = self.view_changes.get(&self.view) else {
+            return Vec::new();
+        };
+        if view_changes.len() <= 2 * self.f() {
+            return Vec::new();
+        }
+        let mut view_changes = view_changes.values().cloned().collect::<Vec<_>>();
+        view_changes.sort_unstable_by_key(|view_change| view_change.replica_id);
+        let (_, requests) = Self::new_view_requests(&view_changes);
+        let pre_prepares = requests
+            .into_iter()
+            .map(|(op_num, request)| {
+                self.sign(PrePrepare {
+                    view: self.view,
+                    op_num,
+                    request,
+                    signature: Default::default(),
+                })
+            })
+            .collect();
+        let new_view = self.sign(NewView {
+            view: self.view,
+            view_changes,
+            pre_prepares,
+            signature: Default::default(),
+        });
+        let mut effects = vec![NodeEffect::Broadcast(Message::NewView(new_view.clone()))];
+        effects.extend(self.enter_view(new_view));
+        effects
+    }
+
+    fn verify_new_view(&self, new_view: NewView) -> Option<NewView> {
+        let primary = self.primary(new_view.view);
+        let new_view = self.verify(new_view, primary)?;
+        let mut replicas = HashSet::new();
+        for view_change in &new_view.view_changes {
+            if view_change.view != new_view.view || !replicas.insert(view_change.replica_id) {
+                return None;
+            }
+            self.verify_view_change(view_change.clone())?;
+        }
+        if replicas.len() <= 2 * self.f() {
+            return None;
+        }
+        let (_, requests) = Self::new_view_requests(&new_view.view_changes);
+        if requests.len() != new_view.pre_prepares.len() {
+            return None;
+        }
+        for ((op_num, request), pre_prepare) in requests.into_iter().zip(&new_view.pre_prepares) {
+            if pre_prepare.view != new_view.view
+                || pre_prepare.op_num != op_num
+                || digest(&pre_prepare.request) != digest(&request)
+            {
+                return None;
+            }
+            self.verify(pre_prepare.clone(), primary)?;
+        }
+        Some(new_view)
+    }
+
+    fn handle_new_view(&mut self, new_view: NewView) -> Vec<NodeEffect<Message>> {
+        if new_view.view < self.view
+            || (new_view.view == self.view && self.status == Status::Normal)
+            || self.primary(new_view.view) == self.id
+        {
+            return Vec::new();
+        }
+        let Some(new_view) = self.verify_new_view(new_view) else {
+            return Vec::new();
+        };
+        self.enter_view(new_view)
+    }
+
+    fn enter_view(&mut self, new_view: NewView) -> Vec<NodeEffect<Message>> {
+        let (checkpoint_op_num, _) = Self::new_view_requests(&new_view.view_changes);
+        self.view = new_view.view;
+        self.normal_view = self.view;
+        self.status = Status::Normal;
+        self.ticked = 0;
+        self.view_changes = self.view_changes.split_off(&(self.view + 1));
+        self.op_num = new_view
+            .pre_prepares
+            .last()
+            .map(|pre_prepare| pre_prepare.op_num)
+            .unwrap_or(checkpoint_op_num);
+        for (_, entry) in self.log.range_mut(checkpoint_op_num + 1..) {
+            entry.pre_prepare = None;
+        }
+        let mut effects = Vec::new();
+        for pre_prepare in new_view.pre_prepares {
+            let op_num = pre_prepare.op_num;
+            if !self.in_window(op_num) {
+                continue;
+            }
+            self.insert_pre_prepare(pre_prepare);
+            if !self.is_primary() {
+                effects.extend(self.prepare(op_num));
+            }
+        }
+        effects.extend(self.execute());
+        effects
+    }
+
+    fn handle_tick(&mut self) -> Vec<NodeEffect<Message>> {
+        let timeout = match self.status {
+            Status::Normal if self.waiting.is_empty() => return Vec::new(),
+            Status::Normal => VIEW_CHANGE_TICKS,
+            // wait twice as long for every consecutive failed view change before giving up on the
+            // new primary, so the correct replicas eventually stay in the same view for long enough
+            Status::ViewChange => {
+                VIEW_CHANGE_TICKS << (self.view - self.normal_view - 1).min(MAX_BACKOFF_SHIFT)
+            }
+        };
+        self.ticked += 1;
+        if self.ticked < timeout {
+            return Vec::new();
+        }
+        self.start_view_change(self.view + 1)
+    }
+}
+
+impl Protocol<NodeEvent<Message>> for Replica {
+    type Effect = Vec<NodeEffect<Message>>;
+
+    fn update(&mut self, event: NodeEvent<Message>) -> Self::Effect {
+        match event {
+            NodeEvent::Init => Vec::new(),
+            NodeEvent::Tick => self.handle_tick(),
+            NodeEvent::Handle(Message::Request(request)) => self.handle_request(request),
+            NodeEvent::Handle(Message::PrePrepare(pre_prepare)) => {
+                self.handle_pre_prepare(pre_prepare)
+            }
+            NodeEvent::Handle(Message::Prepare(prepare)) => self.handle_prepare(prepare),
+            NodeEvent::Handle(Message::Commit(commit)) => self.handle_commit(commit),
+            NodeEvent::Handle(Message::Checkpoint(checkpoint)) => {
+                self.handle_checkpoint(checkpoint)
+            }
+            NodeEvent::Handle(Message::ViewChange(view_change)) => {
+                self.handle_view_change(view_change)
+            }
+            NodeEvent::Handle(Message::NewView(new_view)) => self.handle_new_view(new_view),
+            NodeEvent::Handle(Message::Reply(_)) => {
+                UnexpectedMessage("reply").report();
+                Vec::new()
+            }
+            NodeEvent::Timeout(_) => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        app,
+        byzantine::{Adversary, Byzantine},
+        crypto::{public_keys, secret_key, sign},
+        node::{Request, Workload},
+        protocol::OneOf,
+        simulate::{
+            cluster::{self, assert_results, replica, replica_mut, workload, Cluster, Ops},
+            PartitionMode,
+        },
+        App,
+        NodeAddr::{TestClient, TestReplica},
+        NodeEffect, NodeEvent, Protocol, Simulate,
+    };
+
+    use super::{
+        Checkpoint, Client, Commit, Message, PrePrepare, Replica, Reply, ViewChange,
+        CHECKPOINT_INTERVAL,
+    };
+
+    fn simulate(
+        num_client: u32,
+        num_replica: u32,
+        num_op: usize,
+    ) -> Cluster<Client, Replica, Message> {
+        cluster::new(
+            num_client,
+            num_replica,
+            num_op,
+            |i, replicas| Client::new(i, TestClient(i), replicas, public_keys(num_replica as _)),
+            |i, replicas, app| {
+                Replica::new(
+                    i,
+                    replicas,
+                    secret_key(i),
+                    public_keys(num_replica as _),
+                    app,
+                )
+            },
+        )
+    }
+
+    #[test]
+    fn single_op() {
+        let mut simulate = simulate(1, 4, 1);
+        simulate.init();
+        while simulate.progress() {}
+        assert_results(&simulate, 1, 1);
+    }
+
+    // a reply from a faulty peer is ignored instead of aborting the replica
+    #[test]
+    fn ignore_reply() {
+        let mut simulate = simulate(1, 4, 1);
+        simulate.init();
+        while simulate.progress() {}
+        let reply = Reply {
+            view: 0,
+            seq: 1,
+            replica_id: 1,
+            result: b"client 0 op 0".to_vec().into(),
+            signature: Default::default(),
+        };
+        assert!(replica_mut(&mut simulate, 0)
+            .update(NodeEvent::Handle(Message::Reply(reply)))
+            .is_empty());
+    }
+
+    #[test]
+    fn checkpoint() {
+        let num_op = CHECKPOINT_INTERVAL as usize + 1;
+        let mut simulate = simulate(2, 4, num_op);
+        simulate.init();
+        while simulate.progress() {}
+        assert_results(&simulate, 2, num_op);
+        for i in 0..4 {
+            let replica = replica(&simulate, i);
+            assert_eq!(replica.checkpoint_op_num, 2 * CHECKPOINT_INTERVAL);
+            assert_eq!(replica.log.len(), 2);
+        }
+    }
+
+    // messages far ahead of the replica are dropped instead of collected
+    #[test]
+    fn far_ahead_messages() {
+        let mut simulate = simulate(1, 4, 1);
+        simulate.init();
+        while simulate.progress() {}
+        let mut checkpoint = Checkpoint {
+            op_num: 10 * CHECKPOINT_INTERVAL,
+            digest: Default::default(),
+            replica_id: 1,
+            signature: Default::default(),
+        };
+        sign(&mut checkpoint, &secret_key(1));
+        let mut view_change = ViewChange {
+            view: 100,
+            checkpoint_op_num: 0,
+            checkpoint_proof: Vec::new(),
+            prepared: Vec::new(),
+            replica_id: 1,
+            signature: Default::default(),
+        };
+        sign(&mut view_change, &secret_key(1));
+        let replica = replica_mut(&mut simulate, 0);
+        replica.update(NodeEvent::Handle(Message::Checkpoint(checkpoint)));
+        replica.update(NodeEvent::Handle(Message::ViewChange(view_change)));
+        assert!(replica.checkpoints.is_empty());
+        assert!(replica.view_changes.is_empty());
+    }
+
+    // without state transfer, a replica that misses the ops before a stable checkpoint stays
+    // behind, and the others keep going with it as the faulty one
+    #[test]
+    fn lagging_replica() {
+        let num_op = 2 * CHECKPOINT_INTERVAL as usize + 2;
+        let mut simulate = simulate(1, 4, num_op);
+        simulate.partition(
+            [[
+                TestClient(0),
+                TestReplica(0),
+                TestReplica(1),
+                TestReplica(2),
+            ]],
+            PartitionMode::Drop,
+        );
+        simulate.init();
+        while workload(&simulate, 0).results.len() < num_op - 1 {
+            simulate.progress();
+        }
+        simulate.heal();
+        while simulate.progress() {}
+        assert_results(&simulate, 1, num_op);
+        for i in 0..3 {
+            assert_eq!(replica(&simulate, i).execute_num, num_op as u32);
+        }
+        assert_eq!(replica(&simulate, 3).execute_num, 0);
+    }
+
+    #[test]
+    fn view_change() {
+        let mut simulate = simulate(1, 4, 2);
+        simulate.init();
+        simulate.tick(TestClient(0));
+        simulate.tick(TestClient(0));
+        // primary pre-prepares the request, and backups start waiting for it
+        for _ in 0..5 {
+            simulate.progress();
+        }
+        for _ in 0..10 {
+            for i in 1..4 {
+                simulate.tick(TestReplica(i));
+            }
+        }
+        while simulate.progress() {}
+        for i in 0..4 {
+            assert_eq!(replica(&simulate, i).view, 1);
+        }
+        // the request was not prepared by anyone before view change
+        simulate.tick(TestClient(0));
+        while simulate.progress() {}
+        assert_results(&simulate, 1, 2);
+        for i in 0..4 {
+            assert_eq!(replica(&simulate, i).execute_num, 2);
+        }
+    }
+
+    #[test]
+    fn view_change_backoff() {
+        // a backup that hears from no one else keeps failing to change view
+        let mut replica = Replica::new(
+            1,
+            (0..4).map(TestReplica).collect(),
+            secret_key(1),
+            public_keys(4),
+            App::Echo(app::Echo),
+        );
+        replica.update(NodeEvent::Handle(Message::Request(Request {
+            client_id: 0,
+            client_addr: TestClient(0),
+            seq: 1,
+            op: Default::default(),
+        })));
+        let mut changed = Vec::new();
+        for i in 1..=150 {
+            let view = replica.view;
+            replica.update(NodeEvent::Tick);
+            if replica.view != view {
+                changed.push(i);
+            }
+        }
+        // suspect the primary of view 0, then wait 10, 20 and 40 ticks for the next primaries
+        assert_eq!(changed, [10, 20, 40, 80]);
+    }
+
+    type ByzantineSimulate = Simulate<
+        OneOf<Workload<Client, Ops>, Byzantine<Replica, Box<dyn Adversary<Message>>>>,
+        Message,
+    >;
+
+    fn byzantine_simulate(
+        num_op: usize,
+        adversaries: [Box<dyn Adversary<Message>>; 4],
+    ) -> ByzantineSimulate {
+        let mut byzantine_simulate = Simulate::default();
+        let mut adversaries = adversaries.into_iter();
+        for (addr, node) in simulate(1, 4, num_op).nodes {
+            let node = match node {
+                OneOf::A(workload) => OneOf::A(workload),
+                OneOf::B(replica) => OneOf::B(Byzantine::new(replica, adversaries.next().unwrap())),
+            };
+            byzantine_simulate.nodes.insert(addr, node);
+        }
+        byzantine_simulate
+    }
+
+    fn run(simulate: &mut ByzantineSimulate, num_op: usize) {
+        simulate.init();
+        assert!(simulate.run_until_predicate(
+            |simulate| cluster::workload(simulate, 0).results.len() == num_op,
+            Duration::from_secs(10),
+        ));
+        let replicas = simulate
+            .nodes
+            .values()
+            .filter_map(|node| match node {
+                OneOf::B(byzantine) => Some(&byzantine.node),
+                OneOf::A(_) => None,
+            })
+            .collect::<Vec<_>>();
+        let execute_num = replicas.iter().map(|replica| replica.execute_num).max();
+        // the faulty replica may fall behind, but no one executes differently
+        let histories = replicas
+            .iter()
+            .filter(|replica| Some(replica.execute_num) == execute_num)
+            .map(|replica| replica.history)
+            .collect::<Vec<_>>();
+        assert!(histories.len() >= 3);
+        assert!(histories.iter().all(|history| *history == histories[0]));
+    }
+
+    // tamper with own votes, forge votes of others, and replay whatever is received
+    #[derive(Default)]
+    struct Liar {
+        received: Vec<Message>,
+    }
+
+    impl Adversary<Message> for Liar {
+        fn receive(&mut self, event: &NodeEvent<Message>) -> bool {
+            if let NodeEvent::Handle(message) = event {
+                self.received.push(message.clone())
+            }
+            true
+        }
+
+        fn send(&mut self, effect: NodeEffect<Message>) -> Vec<NodeEffect<Message>> {
+            let effect = match effect {
+                NodeEffect::Broadcast(Message::Prepare(mut prepare)) => {
+                    prepare.digest = Default::default();
+                    NodeEffect::Broadcast(Message::Prepare(prepare))
+                }
+                NodeEffect::Broadcast(Message::Commit(mut commit)) => {
+                    commit.digest = Default::default();
+                    NodeEffect::Broadcast(Message::Commit(commit))
+                }
+                effect => effect,
+            };
+            vec![effect]
+        }
+
+        fn tick(&mut self) -> Vec<NodeEffect<Message>> {
+            let forged = Message::Commit(Commit {
+                view: 0,
+                op_num: 1,
+                digest: Default::default(),
+                replica_id: 1,
+                signature: Default::default(),
+            });
+            self.received
+                .drain(..)
+                .chain([forged])
+                .map(NodeEffect::Broadcast)
+                .collect()
+        }
+    }
+
+    #[test]
+    fn byzantine_backup() {
+        let mut simulate = byzantine_simulate(
+            5,
+            [
+                Box::new(()),
+                Box::new(()),
+                Box::new(()),
+                Box::<Liar>::default(),
+            ],
+        );
+        run(&mut simulate, 5);
+    }
+
+    // pre-prepare the request to replica 1, and a null request to the others
+    struct Equivocator;
+
+    impl Adversary<Message> for Equivocator {
+        fn send(&mut self, effect: NodeEffect<Message>) -> Vec<NodeEffect<Message>> {
+            let NodeEffect::Broadcast(Message::PrePrepare(pre_prepare)) = effect else {
+                return vec![effect];
+            };
+            let mut null_pre_prepare = PrePrepare {
+                request: None,
+                ..pre_prepare.clone()
+            };
+            sign(&mut null_pre_prepare, &secret_key(0));
+            vec![
+                NodeEffect::Send(TestReplica(1), Message::PrePrepare(pre_prepare)),
+                NodeEffect::Send(
+                    TestReplica(2),
+                    Message::PrePrepare(null_pre_prepare.clone()),
+                ),
+                NodeEffect::Send(TestReplica(3), Message::PrePrepare(null_pre_prepare)),
+            ]
+        }
+    }
+
+    #[test]
+    fn byzantine_primary() {
+        let mut simulate = byzantine_simulate(
+            2,
+            [
+                Box::new(Equivocator),
+                Box::new(()),
+                Box::new(()),
+                Box::new(()),
+            ],
+        );
+        run(&mut simulate, 2);
+        let OneOf::B(byzantine) = &simulate.nodes[&TestReplica(1)] else {
+            unreachable!()
+        };
+        assert_ne!(byzantine.node.view, 0);
+    }
+}
@@ -178,6 +178,25 @@ impl<E> Generate for channel::Receiver<E> {
     }
 }
 
+// drain the channel into batches of at most `.1` events, only block for the first one of each
+pub struct Batched<E>(pub channel::Receiver<E>, pub usize);
+
+impl<E> Generate for Batched<E> {
+    type Event<'a> = Vec<E>;
+
+    fn deploy<P>(&mut self, protocol: &mut P)
+    where
+        P: for<'a> Protocol<Self::Event<'a>>,
+    {
+        assert_ne!(self.1, 0);
+        for event in self.0.iter() {
+            let mut events = vec![event];
+            events.extend(self.0.try_iter().take(self.1 - 1));
+            protocol.update(events);
+        }
+    }
+}
+
 pub trait ReactiveGenerate<E> {
     type Event<'a>;
 
@@ -0,0 +1,777 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app::App,
+    node::{ClientEffect, ClientEvent, ClientState, Request, UnexpectedMessage},
+    NodeAddr, NodeEffect, NodeEvent, Protocol,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reply {
+    seq: u32,
+    leader_id: u32,
+    result: Box<[u8]>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    term: u32,
+    // `None` for the no-op that a new leader appends to commit the entries of previous terms
+    request: Option<Request>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestVote {
+    term: u32,
+    candidate_id: u32,
+    last_log_index: u32,
+    last_log_term: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RequestVoteReply {
+    term: u32,
+    vote_granted: bool,
+    replica_id: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppendEntries {
+    term: u32,
+    leader_id: u32,
+    prev_log_index: u32,
+    prev_log_term: u32,
+    entries: Vec<Entry>,
+    leader_commit: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppendEntriesReply {
+    term: u32,
+    success: bool,
+    // if succeeded, the last index that matches leader's log, otherwise a hint of where leader
+    // should retry from
+    match_index: u32,
+    replica_id: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    last_included_index: u32,
+    last_included_term: u32,
+    app: Box<[u8]>,
+    client_table: Vec<(u32, Reply)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstallSnapshot {
+    term: u32,
+    leader_id: u32,
+    snapshot: Snapshot,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Message {
+    Request(Request),
+    Reply(Reply),
+    RequestVote(RequestVote),
+    RequestVoteReply(RequestVoteReply),
+    AppendEntries(AppendEntries),
+    AppendEntriesReply(AppendEntriesReply),
+    // replied with `AppendEntriesReply`
+    InstallSnapshot(InstallSnapshot),
+}
+
+pub struct Client {
+    pub state: ClientState,
+    replicas: Box<[NodeAddr]>,
+    leader_id: u32,
+}
+
+impl Client {
+    pub fn new(id: u32, addr: NodeAddr, replicas: Box<[NodeAddr]>) -> Self {
+        Self {
+            state: ClientState::new(id, addr),
+            replicas,
+            leader_id: 0,
+        }
+    }
+}
+
+impl Protocol<ClientEvent<Message>> for Client {
+    type Effect = Option<ClientEffect<Message>>;
+
+    fn update(&mut self, event: ClientEvent<Message>) -> Self::Effect {
+        match event {
+            ClientEvent::Op(op) => {
+                let request = self.state.invoke(op);
+                Some(ClientEffect::Node(NodeEffect::Send(
+                    self.replicas[self.leader_id as usize],
+                    Message::Request(request),
+                )))
+            }
+            ClientEvent::Node(NodeEvent::Init) => None,
+            ClientEvent::Node(NodeEvent::Tick) => {
+                let request = self.state.tick()?;
+                // the leader may have changed, and only the current one will respond
+                Some(ClientEffect::Node(NodeEffect::Broadcast(Message::Request(
+                    request,
+                ))))
+            }
+            ClientEvent::Node(NodeEvent::Handle(Message::Reply(reply))) => {
+                if !self.state.is_outstanding(reply.seq) {
+                    return None;
+                }
+                self.state.complete();
+                self.leader_id = reply.leader_id;
+                Some(ClientEffect::Result(reply.result))
+            }
+            ClientEvent::Node(NodeEvent::Handle(_)) => None,
+            ClientEvent::Node(NodeEvent::Timeout(_)) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+pub struct Replica {
+    id: u32,
+    replicas: Box<[NodeAddr]>,
+    app: App,
+    role: Role,
+    term: u32,
+    voted_for: Option<u32>,
+    // the entries after the snapshot, i.e., `log[0]` has index `snapshot.last_included_index + 1`
+    log: Vec<Entry>,
+    snapshot: Snapshot,
+    commit_index: u32,
+    last_applied: u32,
+    client_table: HashMap<u32, Reply>,
+    votes: HashSet<u32>,
+    next_index: HashMap<u32, u32>,
+    match_index: HashMap<u32, u32>,
+    // leader: ticks since the last heartbeat
+    // otherwise: ticks since the last message from leader (or since the election started)
+    ticked: u32,
+}
+
+const HEARTBEAT_TICKS: u32 = 2;
+const ELECTION_TICKS: u32 = 10;
+// take a snapshot when there are this many applied entries in the log
+const COMPACTION_ENTRIES: u32 = 100;
+
+impl Replica {
+    pub fn new(id: u32, replicas: Box<[NodeAddr]>, app: App) -> Self {
+        assert!((id as usize) < replicas.len());
+        let mut replica = Self {
+            id,
+            replicas,
+            role: Role::Follower,
+            // the initial leader is elected in term 1 without an election, as every log is empty
+            term: 1,
+            voted_for: Some(0),
+            log: Default::default(),
+            snapshot: Snapshot {
+                last_included_index: 0,
+                last_included_term: 0,
+                app: app.snapshot(),
+                client_table: Default::default(),
+            },
+            app,
+            commit_index: 0,
+            last_applied: 0,
+            client_table: Default::default(),
+            votes: Default::default(),
+            next_index: Default::default(),
+            match_index: Default::default(),
+            ticked: 0,
+        };
+        if id == 0 {
+            replica.become_leader();
+        }
+        replica
+    }
+
+    // keep the persistent state, i.e., term, vote, log and snapshot, and lose the rest
+    pub fn restart(self) -> Self {
+        let mut app = self.app;
+        app.restore(&self.snapshot.app);
+        Self {
+            app,
+            role: Role::Follower,
+            commit_index: self.snapshot.last_included_index,
+            last_applied: self.snapshot.last_included_index,
+            client_table: self.snapshot.client_table.iter().cloned().collect(),
+            votes: Default::default(),
+            next_index: Default::default(),
+            match_index: Default::default(),
+            ticked: 0,
+            ..self
+        }
+    }
+
+    fn majority(&self) -> usize {
+        self.replicas.len() / 2 + 1
+    }
+
+    fn send_replica(&self, id: u32, message: Message) -> NodeEffect<Message> {
+        NodeEffect::Send(self.replicas[id as usize], message)
+    }
+
+    fn last_log_index(&self) -> u32 {
+        self.snapshot.last_included_index + self.log.len() as u32
+    }
+
+    fn term_at(&self, index: u32) -> Option<u32> {
+        if index == self.snapshot.last_included_index {
+            return Some(self.snapshot.last_included_term);
+        }
+        let offset = index.checked_sub(self.snapshot.last_included_index + 1)?;
+        self.log.get(offset as usize).map(|entry| entry.term)
+    }
+
+    fn entry(&self, index: u32) -> &Entry {
+        &self.log[(index - self.snapshot.last_included_index - 1) as usize]
+    }
+
+    fn observe_term(&mut self, term: u32) {
+        if term > self.term {
+            self.term = term;
+            self.role = Role::Follower;
+            self.voted_for = None;
+        }
+    }
+
+    fn become_leader(&mut self) {
+        self.role = Role::Leader;
+        self.ticked = 0;
+        let next_index = self.last_log_index() + 1;
+        for id in 0..self.replicas.len() as u32 {
+            if id != self.id {
+                self.next_index.insert(id, next_index);
+                self.match_index.insert(id, 0);
+            }
+        }
+    }
+
+    fn start_election(&mut self) -> Vec<NodeEffect<Message>> {
+        self.term += 1;
+        self.role = Role::Candidate;
+        self.voted_for = Some(self.id);
+        self.votes = [self.id].into_iter().collect();
+        self.ticked = 0;
+        vec![NodeEffect::Broadcast(Message::RequestVote(RequestVote {
+            term: self.term,
+            candidate_id: self.id,
+            last_log_index: self.last_log_index(),
+            last_log_term: self.term_at(self.last_log_index()).unwrap(),
+        }))]
+    }
+
+    fn handle_request_vote(&mut self, request_vote: RequestVote) -> Vec<NodeEffect<Message>> {
+        self.observe_term(request_vote.term);
+        let last_log_index = self.last_log_index();
+        let up_to_date = (request_vote.last_log_term, request_vote.last_log_index)
+            >= (self.term_at(last_log_index).unwrap(), last_log_index);
+        let vote_granted = request_vote.term == self.term
+            && up_to_date
+            && self.voted_for.unwrap_or(request_vote.candidate_id) == request_vote.candidate_id;
+        if vote_granted {
+            self.voted_for = Some(request_vote.candidate_id);
+            self.ticked = 0;
+        }
+        vec![self.send_replica(
+            request_vote.candidate_id,
+            Message::RequestVoteReply(RequestVoteReply {
+                term: self.term,
+                vote_granted,
+                replica_id: self.id,
+            }),
+        )]
+    }
+
+    fn handle_request_vote_reply(&mut self, reply: RequestVoteReply) -> Vec<NodeEffect<Message>> {
+        self.observe_term(reply.term);
+        if self.role != Role::Candidate || reply.term != self.term || !reply.vote_granted {
+            return Vec::new();
+        }
+        self.votes.insert(reply.replica_id);
+        if self.votes.len() < self.majority() {
+            return Vec::new();
+        }
+        self.become_leader();
+        self.log.push(Entry {
+            term: self.term,
+            request: None,
+        });
+        self.replicate()
+    }
+
+    fn replicate(&mut self) -> Vec<NodeEffect<Message>> {
+        let replica_id = self.id;
+        let mut effects = (0..self.replicas.len() as u32)
+            .filter(|&id| id != replica_id)
+            .map(|id| self.append_entries(id))
+            .collect::<Vec<_>>();
+        effects.extend(self.advance_commit());
+        effects
+    }
+
+    fn append_entries(&mut self, id: u32) -> NodeEffect<Message> {
+        let next_index = self.next_index[&id];
+        let message = if next_index <= self.snapshot.last_included_index {
+            Message::InstallSnapshot(InstallSnapshot {
+                term: self.term,
+                leader_id: self.id,
+                snapshot: self.snapshot.clone(),
+            })
+        } else {
+            let prev_log_index = next_index - 1;
+            let entries =
+                self.log[(prev_log_index - self.snapshot.last_included_index) as usize..].to_vec();
+            // optimistically assume the entries will be appended, and go back on failure reply
+            self.next_index.insert(id, self.last_log_index() + 1);
+            Message::AppendEntries(AppendEntries {
+                term: self.term,
+                leader_id: self.id,
+                prev_log_index,
+                prev_log_term: self.term_at(prev_log_index).unwrap(),
+                entries,
+                leader_commit: self.commit_index,
+            })
+        };
+        self.send_replica(id, message)
+    }
+
+    fn handle_request(&mut self, request: Request) -> Vec<NodeEffect<Message>> {
+        match self.client_table.get(&request.client_id) {
+            Some(reply) if reply.seq > request.seq => return Vec::new(),
+            Some(reply) if reply.seq == request.seq => {
+                return vec![NodeEffect::Send(
+                    request.client_addr,
+                    Message::Reply(reply.clone()),
+                )]
+            }
+            _ => {}
+        }
+        if self.role != Role::Leader {
+            return Vec::new();
+        }
+        let appended = self.log[(self.last_applied - self.snapshot.last_included_index) as usize..]
+            .iter()
+            .any(|entry| {
+                matches!(
+                    &entry.request,
+                    Some(other) if other.client_id == request.client_id && other.seq >= request.seq
+                )
+            });
+        if appended {
+            return Vec::new();
+        }
+        self.log.push(Entry {
+            term: self.term,
+            request: Some(request),
+        });
+        self.replicate()
+    }
+
+    fn handle_append_entries(&mut self, append_entries: AppendEntries) -> Vec<NodeEffect<Message>> {
+        self.observe_term(append_entries.term);
+        let leader_id = append_entries.leader_id;
+        if append_entries.term < self.term {
+            return vec![self.append_entries_reply(leader_id, false, self.commit_index)];
+        }
+        self.role = Role::Follower;
+        self.ticked = 0;
+
+        let mut index = append_entries.prev_log_index;
+        let mut entries = append_entries.entries.into_iter();
+        if index < self.snapshot.last_included_index {
+            // the entries up to the snapshot are committed, so they must match
+            let skip = (self.snapshot.last_included_index - index).min(entries.len() as u32);
+            entries.by_ref().take(skip as usize).for_each(drop);
+            index += skip;
+        } else if self.term_at(index) != Some(append_entries.prev_log_term) {
+            return vec![self.append_entries_reply(leader_id, false, self.commit_index)];
+        }
+        for entry in entries {
+            index += 1;
+            match self.term_at(index) {
+                Some(term) if term == entry.term => continue,
+                Some(_) => self
+                    .log
+                    .truncate((index - self.snapshot.last_included_index - 1) as usize),
+                None => {}
+            }
+            self.log.push(entry);
+        }
+        let match_index = index.max(self.snapshot.last_included_index);
+        let mut effects = vec![self.append_entries_reply(leader_id, true, match_index)];
+        if append_entries.leader_commit > self.commit_index {
+            self.commit_index = append_entries.leader_commit.min(match_index);
+            effects.extend(self.apply());
+        }
+        effects
+    }
+
+    fn append_entries_reply(
+        &self,
+        leader_id: u32,
+        success: bool,
+        match_index: u32,
+    ) -> NodeEffect<Message> {
+        self.send_replica(
+            leader_id,
+            Message::AppendEntriesReply(AppendEntriesReply {
+                term: self.term,
+                success,
+                match_index,
+                replica_id: self.id,
+            }),
+        )
+    }
+
+    fn handle_append_entries_reply(
+        &mut self,
+        reply: AppendEntriesReply,
+    ) -> Vec<NodeEffect<Message>> {
+        self.observe_term(reply.term);
+        if self.role != Role::Leader || reply.term != self.term {
+            return Vec::new();
+        }
+        if !reply.success {
+            self.next_index
+                .insert(reply.replica_id, reply.match_index + 1);
+            return vec![self.append_entries(reply.replica_id)];
+        }
+        let match_index = self.match_index.get_mut(&reply.replica_id).unwrap();
+        *match_index = (*match_index).max(reply.match_index);
+        let match_index = *match_index;
+        let next_index = self.next_index.get_mut(&reply.replica_id).unwrap();
+        *next_index = (*next_index).max(match_index + 1);
+        self.advance_commit()
+    }
+
+    fn advance_commit(&mut self) -> Vec<NodeEffect<Message>> {
+        // only the entries of the current term are committed by counting replicas
+        for index in (self.commit_index + 1..=self.last_log_index()).rev() {
+            if self.term_at(index) != Some(self.term) {
+                break;
+            }
+            let count = self
+                .match_index
+                .values()
+                .filter(|&&match_index| match_index >= index)
+                .count()
+                + 1;
+            if count >= self.majority() {
+                self.commit_index = index;
+                return self.apply();
+            }
+        }
+        Vec::new()
+    }
+
+    fn apply(&mut self) -> Vec<NodeEffect<Message>> {
+        let mut effects = Vec::new();
+        while self.last_applied < self.commit_index {
+            self.last_applied += 1;
+            let Some(request) = self.entry(self.last_applied).request.clone() else {
+                continue;
+            };
+            // the same request can be appended twice if the client resent it across elections
+            if matches!(self.client_table.get(&request.client_id), Some(reply) if reply.seq >= request.seq)
+            {
+                continue;
+            }
+            let reply = Reply {
+                seq: request.seq,
+                leader_id: self.id,
+                result: self.app.execute(&request.op),
+            };
+            if self.role == Role::Leader {
+                effects.push(NodeEffect::Send(
+                    request.client_addr,
+                    Message::Reply(reply.clone()),
+                ));
+            }
+            self.client_table.insert(request.client_id, reply);
+        }
+        if self.last_applied - self.snapshot.last_included_index >= COMPACTION_ENTRIES {
+            self.compact();
+        }
+        effects
+    }
+
+    fn compact(&mut self) {
+        let last_included_term = self.term_at(self.last_applied).unwrap();
+        self.log
+            .drain(..(self.last_applied - self.snapshot.last_included_index) as usize);
+        self.snapshot = Snapshot {
+            last_included_index: self.last_applied,
+            last_included_term,
+            app: self.app.snapshot(),
+            client_table: self
+                .client_table
+                .iter()
+                .map(|(&client_id, reply)| (client_id, reply.clone()))
+                .collect(),
+        };
+    }
+
+    fn handle_install_snapshot(
+        &mut self,
+        install_snapshot: InstallSnapshot,
+    ) -> Vec<NodeEffect<Message>> {
+        self.observe_term(install_snapshot.term);
+        let leader_id = install_snapshot.leader_id;
+        if install_snapshot.term < self.term {
+            return vec![self.append_entries_reply(leader_id, false, self.commit_index)];
+        }
+        self.role = Role::Follower;
+        self.ticked = 0;
+        let snapshot = install_snapshot.snapshot;
+        let last_included_index = snapshot.last_included_index;
+        if last_included_index > self.last_applied {
+            if self.term_at(last_included_index) == Some(snapshot.last_included_term) {
+                self.log
+                    .drain(..(last_included_index - self.snapshot.last_included_index) as usize);
+            } else {
+                self.log.clear();
+            }
+            self.app.restore(&snapshot.app);
+            self.client_table = snapshot.client_table.iter().cloned().collect();
+            self.commit_index = self.commit_index.max(last_included_index);
+            self.last_applied = last_included_index;
+            self.snapshot = snapshot;
+        }
+        vec![self.append_entries_reply(leader_id, true, last_included_index)]
+    }
+
+    fn handle_tick(&mut self) -> Vec<NodeEffect<Message>> {
+        self.ticked += 1;
+        match self.role {
+            Role::Leader => {
+                if self.ticked < HEARTBEAT_TICKS {
+                    return Vec::new();
+                }
+                self.ticked = 0;
+                self.replicate()
+            }
+            // stagger by id to avoid split votes
+            Role::Follower | Role::Candidate => {
+                if self.ticked < ELECTION_TICKS + 2 * self.id {
+                    return Vec::new();
+                }
+                self.start_election()
+            }
+        }
+    }
+}
+
+impl Protocol<NodeEvent<Message>> for Replica {
+    type Effect = Vec<NodeEffect<Message>>;
+
+    fn update(&mut self, event: NodeEvent<Message>) -> Self::Effect {
+        match event {
+            NodeEvent::Init => Vec::new(),
+            NodeEvent::Tick => self.handle_tick(),
+            NodeEvent::Handle(Message::Request(request)) => self.handle_request(request),
+            NodeEvent::Handle(Message::RequestVote(request_vote)) => {
+                self.handle_request_vote(request_vote)
+            }
+            NodeEvent::Handle(Message::RequestVoteReply(reply)) => {
+                self.handle_request_vote_reply(reply)
+            }
+            NodeEvent::Handle(Message::AppendEntries(append_entries)) => {
+                self.handle_append_entries(append_entries)
+            }
+            NodeEvent::Handle(Message::AppendEntriesReply(reply)) => {
+                self.handle_append_entries_reply(reply)
+            }
+            NodeEvent::Handle(Message::InstallSnapshot(install_snapshot)) => {
+                self.handle_install_snapshot(install_snapshot)
+            }
+            NodeEvent::Handle(Message::Reply(_)) => {
+                UnexpectedMessage("reply").report();
+                Vec::new()
+            }
+            NodeEvent::Timeout(_) => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        app,
+        protocol::OneOf,
+        simulate::{
+            cluster::{self, assert_results, replica, replica_mut, Cluster},
+            PartitionMode,
+        },
+        App,
+        NodeAddr::{TestClient, TestReplica},
+        NodeEvent, Protocol,
+    };
+
+    use super::{Client, Message, Replica, Reply, Role, COMPACTION_ENTRIES};
+
+    fn simulate(
+        num_client: u32,
+        num_replica: u32,
+        num_op: usize,
+    ) -> Cluster<Client, Replica, Message> {
+        cluster::new(
+            num_client,
+            num_replica,
+            num_op,
+            |i, replicas| Client::new(i, TestClient(i), replicas),
+            Replica::new,
+        )
+    }
+
+    #[test]
+    fn single_op() {
+        let mut simulate = simulate(1, 3, 1);
+        simulate.init();
+        while simulate.progress() {}
+        assert_results(&simulate, 1, 1);
+    }
+
+    // a reply from a faulty peer is ignored instead of aborting the replica
+    #[test]
+    fn ignore_reply() {
+        let mut simulate = simulate(1, 3, 1);
+        simulate.init();
+        while simulate.progress() {}
+        let reply = Reply {
+            seq: 1,
+            leader_id: 0,
+            result: b"client 0 op 0".to_vec().into(),
+        };
+        assert!(replica_mut(&mut simulate, 0)
+            .update(NodeEvent::Handle(Message::Reply(reply)))
+            .is_empty());
+    }
+
+    #[test]
+    fn leader_election() {
+        let mut simulate = simulate(1, 3, 2);
+        // the lowest-id follower times out first
+        for _ in 0..12 {
+            simulate.tick(TestReplica(1));
+        }
+        while simulate.progress() {}
+        assert_eq!(replica(&simulate, 0).role, Role::Follower);
+        assert_eq!(replica(&simulate, 1).role, Role::Leader);
+        assert_eq!(replica(&simulate, 1).term, 2);
+        // the client sends to the old leader until it is told about the new one
+        simulate.init();
+        while simulate.progress() {}
+        simulate.tick(TestClient(0));
+        simulate.tick(TestClient(0));
+        while simulate.progress() {}
+        assert_results(&simulate, 1, 2);
+    }
+
+    #[test]
+    fn isolated_leader() {
+        let mut simulate = simulate(1, 3, 5);
+        simulate.partition(
+            [
+                vec![TestClient(0), TestReplica(1), TestReplica(2)],
+                vec![TestReplica(0)],
+            ],
+            PartitionMode::Drop,
+        );
+        simulate.init();
+        while simulate.progress() {}
+        for _ in 0..12 {
+            simulate.tick(TestReplica(1));
+        }
+        while simulate.progress() {}
+        assert_eq!(replica(&simulate, 1).role, Role::Leader);
+        simulate.tick(TestClient(0));
+        simulate.tick(TestClient(0));
+        while simulate.progress() {}
+        assert_results(&simulate, 1, 5);
+        // the old leader still believes it is leading
+        assert_eq!(replica(&simulate, 0).role, Role::Leader);
+
+        simulate.heal();
+        simulate.tick(TestReplica(1));
+        simulate.tick(TestReplica(1));
+        while simulate.progress() {}
+        assert_eq!(replica(&simulate, 0).role, Role::Follower);
+        assert_eq!(replica(&simulate, 0).term, 2);
+        assert_eq!(
+            replica(&simulate, 0).last_applied,
+            replica(&simulate, 1).last_applied
+        );
+    }
+
+    #[test]
+    fn restart() {
+        let mut simulate = simulate(1, 3, 5);
+        simulate.init();
+        while simulate.progress() {}
+        let OneOf::B(crashed) = simulate.crash(TestReplica(0)) else {
+            unreachable!()
+        };
+        simulate.restart(TestReplica(0), OneOf::B(crashed.restart()));
+        assert_eq!(replica(&simulate, 0).last_applied, 0);
+        assert_eq!(replica(&simulate, 0).log.len(), 5);
+
+        // the restarted leader steps down and the new leader brings it up to date
+        for _ in 0..12 {
+            simulate.tick(TestReplica(1));
+        }
+        while simulate.progress() {}
+        simulate.tick(TestReplica(1));
+        simulate.tick(TestReplica(1));
+        while simulate.progress() {}
+        assert_eq!(replica(&simulate, 1).role, Role::Leader);
+        // 5 ops and the no-op of the new leader
+        assert_eq!(replica(&simulate, 0).last_applied, 6);
+        assert_eq!(replica(&simulate, 0).client_table.len(), 1);
+    }
+
+    #[test]
+    fn compaction() {
+        let num_op = COMPACTION_ENTRIES as usize;
+        let mut simulate = simulate(2, 3, num_op);
+        simulate.init();
+        while simulate.progress() {}
+        assert_results(&simulate, 2, num_op);
+        // followers apply the last entries on the next heartbeat
+        simulate.tick(TestReplica(0));
+        simulate.tick(TestReplica(0));
+        while simulate.progress() {}
+        for i in 0..3 {
+            let replica = replica(&simulate, i);
+            assert_eq!(replica.last_applied, 2 * COMPACTION_ENTRIES);
+            assert_eq!(replica.snapshot.last_included_index, 2 * COMPACTION_ENTRIES);
+            assert!(replica.log.is_empty());
+        }
+
+        // a follower that lost its state catches up by installing leader's snapshot
+        let replicas = (0..3).map(TestReplica).collect();
+        simulate.nodes.insert(
+            TestReplica(2),
+            OneOf::B(Replica::new(2, replicas, App::Echo(app::Echo))),
+        );
+        simulate.tick(TestReplica(0));
+        simulate.tick(TestReplica(0));
+        while simulate.progress() {}
+        assert_eq!(replica(&simulate, 2).last_applied, 2 * COMPACTION_ENTRIES);
+        assert_eq!(replica(&simulate, 2).client_table.len(), 2);
+    }
+}
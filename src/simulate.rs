@@ -1,11 +1,61 @@
-use std::collections::{HashMap, VecDeque};
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap, HashSet, VecDeque},
+    hash::{Hash, Hasher},
+    time::Duration,
+};
 
-use crate::{protocol::Composite, NodeAddr, NodeEffect, NodeEvent, Protocol};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{node::TICK_INTERVAL, protocol::Composite, NodeAddr, NodeEffect, NodeEvent, Protocol};
 
 pub struct Simulate<N, M> {
-    pub nodes: HashMap<NodeAddr, N>,
-    messages: VecDeque<(NodeAddr, M)>,
-    tick_count: HashMap<NodeAddr, u32>,
+    // ordered so that initialization and broadcast fan-out are deterministic
+    pub nodes: BTreeMap<NodeAddr, N>,
+    // (deliver time, source, destination, message)
+    messages: VecDeque<(Duration, NodeAddr, NodeAddr, M)>,
+    tick_count: BTreeMap<NodeAddr, u32>,
+    rng: StdRng,
+    // deliver pending messages in random order instead of FIFO, or in virtual time the ones that
+    // arrive at the same time
+    random_order: bool,
+    fault: Fault,
+    // (source, destination) => fault that overrides `fault` on the link
+    link_faults: HashMap<(NodeAddr, NodeAddr), Fault>,
+    // address => group index, messages are only delivered within a group
+    partition: Option<HashMap<NodeAddr, usize>>,
+    partition_mode: PartitionMode,
+    // messages blocked by the partition in `Hold` mode, which are delivered after healing
+    held_messages: Vec<(Duration, NodeAddr, NodeAddr, M)>,
+    // virtual time, only advanced by `run_until*`
+    now: Duration,
+    // (min, max) of the uniformly distributed message latency
+    latency: (Duration, Duration),
+    next_tick: BTreeMap<NodeAddr, Duration>,
+    // (address, timer id) => virtual time to time out
+    timers: BTreeMap<(NodeAddr, u32), Duration>,
+}
+
+// the probabilities that a message is affected by each kind of fault when it is about to be
+// delivered
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Fault {
+    pub drop: f64,
+    pub duplicate: f64,
+    // a delayed message is sent again instead of being delivered, i.e., moved to the back of the
+    // queue, and takes another latency to arrive
+    pub delay: f64,
+}
+
+impl Fault {
+    fn is_none(&self) -> bool {
+        self.drop == 0. && self.duplicate == 0. && self.delay == 0.
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionMode {
+    Drop,
+    Hold,
 }
 
 impl<N, M> Default for Simulate<N, M> {
@@ -14,40 +64,131 @@ impl<N, M> Default for Simulate<N, M> {
             nodes: Default::default(),
             tick_count: Default::default(),
             messages: Default::default(),
+            rng: StdRng::seed_from_u64(0),
+            random_order: false,
+            fault: Default::default(),
+            link_faults: Default::default(),
+            partition: None,
+            partition_mode: PartitionMode::Drop,
+            held_messages: Default::default(),
+            now: Duration::ZERO,
+            latency: (Duration::from_millis(1), Duration::from_millis(1)),
+            next_tick: Default::default(),
+            timers: Default::default(),
         }
     }
 }
 
 impl<N, M> Simulate<N, M> {
+    // the same seed results in the same interleaving, as long as nodes are deterministic
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            random_order: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn set_fault(&mut self, fault: Fault) {
+        self.fault = fault;
+    }
+
+    pub fn set_link_fault(&mut self, source: NodeAddr, destination: NodeAddr, fault: Fault) {
+        self.link_faults.insert((source, destination), fault);
+    }
+
+    pub fn set_latency(&mut self, min: Duration, max: Duration) {
+        assert!(min <= max);
+        self.latency = (min, max);
+    }
+
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    // the addresses that do not appear in any group are isolated from everyone
+    pub fn partition<G>(&mut self, groups: impl IntoIterator<Item = G>, mode: PartitionMode)
+    where
+        G: IntoIterator<Item = NodeAddr>,
+    {
+        self.heal();
+        self.partition = Some(
+            groups
+                .into_iter()
+                .enumerate()
+                .flat_map(|(i, group)| group.into_iter().map(move |addr| (addr, i)))
+                .collect(),
+        );
+        self.partition_mode = mode;
+    }
+
+    pub fn heal(&mut self) {
+        self.partition = None;
+        self.messages.extend(self.held_messages.drain(..));
+    }
+
+    fn is_blocked(&self, source: NodeAddr, destination: NodeAddr) -> bool {
+        let Some(partition) = &self.partition else {
+            return false;
+        };
+        match (partition.get(&source), partition.get(&destination)) {
+            (Some(source_group), Some(destination_group)) => source_group != destination_group,
+            _ => true,
+        }
+    }
+
+    // the crashed node stops receiving messages and ticks, and the messages it has sent are still
+    // in flight
+    // return the crashed node, so its persisted state can be used to restart it
+    pub fn crash(&mut self, addr: NodeAddr) -> N {
+        self.next_tick.remove(&addr);
+        self.timers.retain(|&(timer_addr, _), _| timer_addr != addr);
+        self.nodes.remove(&addr).unwrap()
+    }
+
+    pub fn restart(&mut self, addr: NodeAddr, mut node: N)
+    where
+        N: Protocol<NodeEvent<M>>,
+        N::Effect: Composite<Atom = NodeEffect<M>>,
+        M: Clone,
+    {
+        let effect = node.update(NodeEvent::Init);
+        assert!(self.nodes.insert(addr, node).is_none());
+        self.push_effect(addr, effect);
+    }
+
     pub fn init(&mut self)
     where
         N: Protocol<NodeEvent<M>>,
         N::Effect: Composite<Atom = NodeEffect<M>>,
+        M: Clone,
     {
-        for effect in self
+        for (addr, effect) in self
             .nodes
-            .values_mut()
-            .map(|node| node.update(NodeEvent::Init))
+            .iter_mut()
+            .map(|(&addr, node)| (addr, node.update(NodeEvent::Init)))
             .collect::<Vec<_>>()
         {
-            self.push_effect(effect)
+            self.push_effect(addr, effect)
         }
     }
 
+    // deliver the next message regardless of its deliver time, without advancing virtual time
     pub fn progress(&mut self) -> bool
     where
         N: Protocol<NodeEvent<M>>,
         N::Effect: Composite<Atom = NodeEffect<M>>,
+        M: Clone,
     {
-        let Some((destination, message)) = self.messages.pop_front() else {
+        if self.messages.is_empty() {
             return false;
+        }
+        let index = if self.random_order {
+            self.rng.gen_range(0..self.messages.len())
+        } else {
+            0
         };
-        let effect = self
-            .nodes
-            .get_mut(&destination)
-            .unwrap()
-            .update(NodeEvent::Handle(message));
-        self.push_effect(effect);
+        self.deliver(index);
         true
     }
 
@@ -55,18 +196,736 @@ impl<N, M> Simulate<N, M> {
     where
         N: Protocol<NodeEvent<M>>,
         N::Effect: Composite<Atom = NodeEffect<M>>,
+        M: Clone,
     {
+        // crashed nodes do not tick
+        let Some(node) = self.nodes.get_mut(&addr) else {
+            return;
+        };
         *self.tick_count.entry(addr).or_default() += 1;
-        let effect = self.nodes.get_mut(&addr).unwrap().update(NodeEvent::Tick);
-        self.push_effect(effect);
+        let effect = node.update(NodeEvent::Tick);
+        self.push_effect(addr, effect);
+    }
+
+    // deliver messages and fire ticks in the order of virtual time, until `time` is reached
+    pub fn run_until(&mut self, time: Duration)
+    where
+        N: Protocol<NodeEvent<M>>,
+        N::Effect: Composite<Atom = NodeEffect<M>>,
+        M: Clone,
+    {
+        while self.step(time) {}
+    }
+
+    // same as `run_until` but stop as soon as `predicate` holds, and return whether it holds
+    pub fn run_until_predicate(
+        &mut self,
+        mut predicate: impl FnMut(&Self) -> bool,
+        time: Duration,
+    ) -> bool
+    where
+        N: Protocol<NodeEvent<M>>,
+        N::Effect: Composite<Atom = NodeEffect<M>>,
+        M: Clone,
+    {
+        loop {
+            if predicate(self) {
+                return true;
+            }
+            if !self.step(time) {
+                return false;
+            }
+        }
+    }
+
+    fn step(&mut self, time: Duration) -> bool
+    where
+        N: Protocol<NodeEvent<M>>,
+        N::Effect: Composite<Atom = NodeEffect<M>>,
+        M: Clone,
+    {
+        // every node ticks at the same interval, starting from when it is first seen
+        for &addr in self.nodes.keys() {
+            self.next_tick
+                .entry(addr)
+                .or_insert(self.now + TICK_INTERVAL);
+        }
+        let message = self
+            .messages
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, (deliver_at, ..))| *deliver_at)
+            .map(|(index, &(deliver_at, ..))| (deliver_at, index));
+        let timer = self
+            .timers
+            .iter()
+            .min_by_key(|(_, &timeout_at)| timeout_at)
+            .map(|(&(addr, id), &timeout_at)| (timeout_at, addr, id));
+        let tick = self
+            .next_tick
+            .iter()
+            .min_by_key(|(_, &tick_at)| tick_at)
+            .map(|(&addr, &tick_at)| (tick_at, addr));
+        let Some(at) = [
+            message.map(|(deliver_at, _)| deliver_at),
+            timer.map(|(timeout_at, ..)| timeout_at),
+            tick.map(|(tick_at, _)| tick_at),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+        .filter(|&at| at <= time) else {
+            self.now = self.now.max(time);
+            return false;
+        };
+        // the delayed messages may be sent earlier than now
+        self.now = self.now.max(at);
+        // prefer messages, then timers, on the same virtual time
+        if let Some((deliver_at, mut index)) = message.filter(|&(deliver_at, _)| deliver_at <= at) {
+            // the messages arriving at the same time are delivered in a random order as well
+            if self.random_order {
+                let indexes = (0..self.messages.len())
+                    .filter(|&index| self.messages[index].0 == deliver_at)
+                    .collect::<Vec<_>>();
+                index = indexes[self.rng.gen_range(0..indexes.len())];
+            }
+            self.deliver(index);
+        } else if let Some((_, addr, id)) = timer.filter(|&(timeout_at, ..)| timeout_at == at) {
+            self.timeout(addr, id);
+        } else {
+            let (_, addr) = tick.unwrap();
+            self.next_tick.insert(addr, at + TICK_INTERVAL);
+            self.tick(addr);
+        }
+        true
+    }
+
+    fn timeout(&mut self, addr: NodeAddr, id: u32)
+    where
+        N: Protocol<NodeEvent<M>>,
+        N::Effect: Composite<Atom = NodeEffect<M>>,
+        M: Clone,
+    {
+        self.timers.remove(&(addr, id)).unwrap();
+        let effect = self
+            .nodes
+            .get_mut(&addr)
+            .unwrap()
+            .update(NodeEvent::Timeout(id));
+        self.push_effect(addr, effect);
+    }
+
+    fn deliver(&mut self, index: usize)
+    where
+        N: Protocol<NodeEvent<M>>,
+        N::Effect: Composite<Atom = NodeEffect<M>>,
+        M: Clone,
+    {
+        let (deliver_at, source, destination, message) = self.messages.remove(index).unwrap();
+        if self.is_blocked(source, destination) {
+            if self.partition_mode == PartitionMode::Hold {
+                self.held_messages
+                    .push((deliver_at, source, destination, message));
+            }
+            return;
+        }
+        let fault = *self
+            .link_faults
+            .get(&(source, destination))
+            .unwrap_or(&self.fault);
+        if self.rng.gen_bool(fault.drop) {
+            return;
+        }
+        if self.rng.gen_bool(fault.delay) {
+            self.send(source, destination, message);
+            return;
+        }
+        if self.rng.gen_bool(fault.duplicate) {
+            self.send(source, destination, message.clone());
+        }
+        // lost if the destination is crashed (or never exists)
+        let Some(node) = self.nodes.get_mut(&destination) else {
+            return;
+        };
+        let effect = node.update(NodeEvent::Handle(message));
+        self.push_effect(destination, effect);
+    }
+
+    // the broadcast group is every replica other than the sender, which matches what `udp::Tx`
+    // is configured with on replicas, and the whole replica group for clients
+    fn broadcast_group(&self, source: NodeAddr) -> impl Iterator<Item = NodeAddr> + '_ {
+        self.nodes
+            .keys()
+            .copied()
+            .filter(move |&addr| matches!(addr, NodeAddr::TestReplica(_)) && addr != source)
     }
 
-    fn push_effect(&mut self, mut effect: impl Composite<Atom = NodeEffect<M>>) {
+    fn send(&mut self, source: NodeAddr, destination: NodeAddr, message: M) {
+        let (min, max) = self.latency;
+        let deliver_at = self.now + self.rng.gen_range(min..=max);
+        self.messages
+            .push_back((deliver_at, source, destination, message))
+    }
+
+    fn push_effect(&mut self, source: NodeAddr, mut effect: impl Composite<Atom = NodeEffect<M>>)
+    where
+        M: Clone,
+    {
         while let Some(basic_effect) = effect.decompose() {
             match basic_effect {
-                NodeEffect::Send(address, message) => self.messages.push_back((address, message)),
-                NodeEffect::Broadcast(_) => todo!(),
+                NodeEffect::Send(destination, message) => self.send(source, destination, message),
+                NodeEffect::Broadcast(message) => {
+                    for destination in self.broadcast_group(source).collect::<Vec<_>>() {
+                        self.send(source, destination, message.clone())
+                    }
+                }
+                NodeEffect::SetTimer(id, duration) => {
+                    self.timers.insert((source, id), self.now + duration);
+                }
+                NodeEffect::CancelTimer(id) => {
+                    self.timers.remove(&(source, id));
+                }
             }
         }
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Choice {
+    Deliver(usize),
+    Drop(usize),
+    Tick(NodeAddr),
+    // fire the timer regardless of its timeout time
+    Timeout(NodeAddr, u32),
+}
+
+impl<N, M> Simulate<N, M> {
+    // the index refers to the pending messages in the order they are sent
+    pub fn choose(&mut self, choice: Choice)
+    where
+        N: Protocol<NodeEvent<M>>,
+        N::Effect: Composite<Atom = NodeEffect<M>>,
+        M: Clone,
+    {
+        match choice {
+            Choice::Deliver(index) => self.deliver(index),
+            Choice::Drop(index) => {
+                self.messages.remove(index).unwrap();
+            }
+            Choice::Tick(addr) => self.tick(addr),
+            Choice::Timeout(addr, id) => self.timeout(addr, id),
+        }
+    }
+
+    // pending messages are hashed as a multiset, so different delivery orders that end up with the
+    // same node states and in-flight messages are considered as the same state
+    // pending timers are hashed without their timeout time, which is not respected by exploration
+    fn fingerprint(&self, node_fingerprint: u64) -> u64
+    where
+        M: serde::Serialize,
+    {
+        let mut messages = self
+            .messages
+            .iter()
+            .map(|(_, source, destination, message)| {
+                bincode::serialize(&(source, destination, message)).unwrap()
+            })
+            .collect::<Vec<_>>();
+        messages.sort_unstable();
+        let mut hasher = DefaultHasher::new();
+        node_fingerprint.hash(&mut hasher);
+        messages.hash(&mut hasher);
+        self.timers.keys().for_each(|timer| timer.hash(&mut hasher));
+        hasher.finish()
+    }
+}
+
+// explore every order of delivering (and, if `drop`, dropping) pending messages, firing pending
+// timers and, if `tick`, ticking live nodes, up to `depth` choices from the state created by
+// `new_simulate`, in breadth-first order
+// ticks are always enabled, so `node_fingerprint` should cover the tick counting states if `tick`
+// `node_fingerprint` hashes the node states, and the states that have been explored are pruned
+// nodes must be deterministic, since every state is reached by replaying its choices from the
+// initial state, which saves requiring `N: Clone`
+// random faults must not be configured, since they depend on the RNG state that is not covered by
+// the fingerprints, and a state would be pruned even if it goes on differently, use `drop` instead
+// return the number of explored states, or the choices that lead to a state violating `invariant`
+pub fn explore<N, M>(
+    new_simulate: impl Fn() -> Simulate<N, M>,
+    node_fingerprint: impl Fn(&Simulate<N, M>) -> u64,
+    invariant: impl Fn(&Simulate<N, M>) -> bool,
+    depth: usize,
+    drop: bool,
+    tick: bool,
+) -> Result<usize, Vec<Choice>>
+where
+    N: Protocol<NodeEvent<M>>,
+    N::Effect: Composite<Atom = NodeEffect<M>>,
+    M: Clone + serde::Serialize,
+{
+    let replay = |choices: &[Choice]| {
+        let mut simulate = new_simulate();
+        for &choice in choices {
+            simulate.choose(choice)
+        }
+        simulate
+    };
+
+    let simulate = new_simulate();
+    assert!(
+        simulate.fault.is_none() && simulate.link_faults.values().all(Fault::is_none),
+        "random faults are not explored"
+    );
+    if !invariant(&simulate) {
+        return Err(Vec::new());
+    }
+    let mut visited = HashSet::new();
+    visited.insert(simulate.fingerprint(node_fingerprint(&simulate)));
+    let mut queue = VecDeque::from([Vec::new()]);
+    while let Some(choices) = queue.pop_front() {
+        if choices.len() == depth {
+            continue;
+        }
+        let simulate = replay(&choices);
+        let mut next_choices = Vec::new();
+        for index in 0..simulate.messages.len() {
+            next_choices.push(Choice::Deliver(index));
+            if drop {
+                next_choices.push(Choice::Drop(index))
+            }
+        }
+        next_choices.extend(
+            simulate
+                .timers
+                .keys()
+                .map(|&(addr, id)| Choice::Timeout(addr, id)),
+        );
+        if tick {
+            next_choices.extend(simulate.nodes.keys().map(|&addr| Choice::Tick(addr)));
+        }
+        for choice in next_choices {
+            let choices = [&*choices, &[choice]].concat();
+            let simulate = replay(&choices);
+            if !visited.insert(simulate.fingerprint(node_fingerprint(&simulate))) {
+                continue;
+            }
+            if !invariant(&simulate) {
+                return Err(choices);
+            }
+            queue.push_back(choices);
+        }
+    }
+    Ok(visited.len())
+}
+
+// the cluster of clients and replicas that the protocols are tested with
+#[cfg(test)]
+pub mod cluster {
+    use crate::{
+        app,
+        linearizability::Record,
+        node::Workload,
+        protocol::OneOf,
+        App,
+        NodeAddr::{self, TestClient, TestReplica},
+        Simulate,
+    };
+
+    pub type Ops = Box<dyn Iterator<Item = Vec<u8>>>;
+
+    pub type Cluster<C, R, M> = Simulate<OneOf<Workload<C, Ops>, R>, M>;
+
+    // client `i` invokes "client {i} op {j}" for `j` in `0..num_op`, and every replica runs the echo
+    // app, so the results are the ops themselves
+    pub fn new<C, R, M>(
+        num_client: u32,
+        num_replica: u32,
+        num_op: usize,
+        new_client: impl Fn(u32, Box<[NodeAddr]>) -> C,
+        new_replica: impl Fn(u32, Box<[NodeAddr]>, App) -> R,
+    ) -> Cluster<C, R, M> {
+        let mut simulate = Simulate::default();
+        let replicas = (0..num_replica).map(TestReplica).collect::<Box<_>>();
+        for i in 0..num_client {
+            simulate.nodes.insert(
+                TestClient(i),
+                OneOf::A(Workload::new_test(
+                    new_client(i, replicas.clone()),
+                    Box::new((0..num_op).map(move |j| format!("client {i} op {j}").into_bytes()))
+                        as Ops,
+                )),
+            );
+        }
+        for i in 0..num_replica {
+            simulate.nodes.insert(
+                TestReplica(i),
+                OneOf::B(new_replica(i, replicas.clone(), App::Echo(app::Echo))),
+            );
+        }
+        simulate
+    }
+
+    pub fn workload<C, R, M>(simulate: &Cluster<C, R, M>, i: u32) -> &Workload<C, Ops> {
+        let OneOf::A(workload) = &simulate.nodes[&TestClient(i)] else {
+            unreachable!()
+        };
+        workload
+    }
+
+    pub fn replica<C, R, M>(simulate: &Cluster<C, R, M>, i: u32) -> &R {
+        let OneOf::B(replica) = &simulate.nodes[&TestReplica(i)] else {
+            unreachable!()
+        };
+        replica
+    }
+
+    pub fn replica_mut<C, R, M>(simulate: &mut Cluster<C, R, M>, i: u32) -> &mut R {
+        let OneOf::B(replica) = simulate.nodes.get_mut(&TestReplica(i)).unwrap() else {
+            unreachable!()
+        };
+        replica
+    }
+
+    pub fn assert_results<C, R, M>(simulate: &Cluster<C, R, M>, num_client: u32, num_op: usize) {
+        for i in 0..num_client {
+            let workload = workload(simulate, i);
+            assert_eq!(workload.results.len(), num_op);
+            for (j, result) in workload.results.iter().enumerate() {
+                assert_eq!(&**result, format!("client {i} op {j}").as_bytes());
+            }
+        }
+    }
+
+    pub fn histories<C, R, M>(simulate: &Cluster<C, R, M>, num_client: u32) -> Vec<&[Record]> {
+        (0..num_client)
+            .map(|i| &workload(simulate, i).history[..])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::hash_map::DefaultHasher,
+        hash::{Hash, Hasher},
+        time::Duration,
+    };
+
+    use super::{explore, Choice, Fault, PartitionMode};
+    use crate::{
+        NodeAddr::{self, TestClient, TestReplica},
+        NodeEffect, NodeEvent, Protocol, Simulate,
+    };
+
+    struct Flood {
+        addr: NodeAddr,
+        received: Vec<NodeAddr>,
+    }
+
+    impl Protocol<NodeEvent<NodeAddr>> for Flood {
+        type Effect = Option<NodeEffect<NodeAddr>>;
+
+        fn update(&mut self, event: NodeEvent<NodeAddr>) -> Self::Effect {
+            match event {
+                NodeEvent::Init if self.addr == TestReplica(0) || self.addr == TestClient(0) => {
+                    Some(NodeEffect::Broadcast(self.addr))
+                }
+                NodeEvent::Handle(source) => {
+                    self.received.push(source);
+                    None
+                }
+                _ => None,
+            }
+        }
+    }
+
+    fn flood(mut simulate: Simulate<Flood, NodeAddr>) -> Simulate<Flood, NodeAddr> {
+        for addr in [
+            TestClient(0),
+            TestReplica(0),
+            TestReplica(1),
+            TestReplica(2),
+        ] {
+            simulate.nodes.insert(
+                addr,
+                Flood {
+                    addr,
+                    received: Default::default(),
+                },
+            );
+        }
+        simulate
+    }
+
+    #[test]
+    fn broadcast() {
+        let mut simulate = flood(Simulate::default());
+        simulate.init();
+        while simulate.progress() {}
+        assert!(simulate.nodes[&TestClient(0)].received.is_empty());
+        assert_eq!(simulate.nodes[&TestReplica(0)].received, [TestClient(0)]);
+        for i in 1..3 {
+            assert_eq!(
+                simulate.nodes[&TestReplica(i)].received,
+                [TestClient(0), TestReplica(0)]
+            );
+        }
+    }
+
+    #[test]
+    fn random_order() {
+        // both with `progress` and in virtual time, where all messages arrive at the same time
+        for run_until in [false, true] {
+            let received = |seed| {
+                let mut simulate = flood(Simulate::with_seed(seed));
+                simulate.init();
+                if run_until {
+                    simulate.run_until(Duration::from_millis(10))
+                } else {
+                    while simulate.progress() {}
+                }
+                (1..3)
+                    .map(|i| simulate.nodes[&TestReplica(i)].received.clone())
+                    .collect::<Vec<_>>()
+            };
+            for seed in 0..10 {
+                let received_once = received(seed);
+                assert_eq!(received(seed), received_once);
+                for mut received in received_once {
+                    received.sort();
+                    assert_eq!(received, [TestClient(0), TestReplica(0)]);
+                }
+            }
+            // some seed should deliver out of FIFO order
+            assert!((0..10).any(|seed| received(seed)[0] == [TestReplica(0), TestClient(0)]));
+        }
+    }
+
+    #[test]
+    fn link_fault() {
+        let mut simulate = flood(Simulate::default());
+        simulate.set_link_fault(
+            TestClient(0),
+            TestReplica(1),
+            Fault {
+                drop: 1.,
+                ..Default::default()
+            },
+        );
+        simulate.set_link_fault(
+            TestClient(0),
+            TestReplica(2),
+            Fault {
+                duplicate: 1.,
+                ..Default::default()
+            },
+        );
+        simulate.init();
+        // every delivery on the duplicating link duplicates again
+        for _ in 0..10 {
+            simulate.progress();
+        }
+        assert_eq!(simulate.nodes[&TestReplica(1)].received, [TestReplica(0)]);
+        assert!(simulate.nodes[&TestReplica(2)].received.len() > 2);
+    }
+
+    #[test]
+    fn partition() {
+        for mode in [PartitionMode::Drop, PartitionMode::Hold] {
+            let mut simulate = flood(Simulate::default());
+            simulate.partition(
+                [
+                    vec![TestClient(0), TestReplica(0), TestReplica(1)],
+                    vec![TestReplica(2)],
+                ],
+                mode,
+            );
+            simulate.init();
+            while simulate.progress() {}
+            assert_eq!(
+                simulate.nodes[&TestReplica(1)].received,
+                [TestClient(0), TestReplica(0)]
+            );
+            assert!(simulate.nodes[&TestReplica(2)].received.is_empty());
+
+            simulate.heal();
+            while simulate.progress() {}
+            let received = &simulate.nodes[&TestReplica(2)].received;
+            match mode {
+                PartitionMode::Drop => assert!(received.is_empty()),
+                PartitionMode::Hold => assert_eq!(received, &[TestClient(0), TestReplica(0)]),
+            }
+        }
+    }
+
+    #[test]
+    fn virtual_time() {
+        let mut simulate = flood(Simulate::default());
+        simulate.set_latency(Duration::from_millis(5), Duration::from_millis(5));
+        simulate.init();
+        simulate.run_until(Duration::from_millis(4));
+        assert_eq!(simulate.now(), Duration::from_millis(4));
+        assert!(simulate.nodes[&TestReplica(1)].received.is_empty());
+
+        simulate.run_until(Duration::from_millis(100));
+        assert_eq!(simulate.now(), Duration::from_millis(100));
+        assert_eq!(simulate.nodes[&TestReplica(1)].received.len(), 2);
+        for addr in simulate.nodes.keys() {
+            assert_eq!(simulate.tick_count[addr], 10);
+        }
+    }
+
+    #[test]
+    fn crash_restart() {
+        let mut simulate = flood(Simulate::default());
+        let replica = simulate.crash(TestReplica(2));
+        simulate.init();
+        while simulate.progress() {}
+        assert_eq!(simulate.nodes[&TestReplica(1)].received.len(), 2);
+        assert!(replica.received.is_empty());
+
+        simulate.restart(TestReplica(2), replica);
+        // the restarted replica 0 broadcasts again on initialization
+        let replica = simulate.crash(TestReplica(0));
+        simulate.restart(TestReplica(0), replica);
+        while simulate.progress() {}
+        assert_eq!(simulate.nodes[&TestReplica(1)].received.len(), 3);
+        assert_eq!(simulate.nodes[&TestReplica(2)].received, [TestReplica(0)]);
+    }
+
+    #[test]
+    fn exploration() {
+        let new_simulate = || {
+            let mut simulate = flood(Simulate::default());
+            simulate.init();
+            simulate
+        };
+        let node_fingerprint = |simulate: &Simulate<Flood, NodeAddr>| {
+            let mut hasher = DefaultHasher::new();
+            for node in simulate.nodes.values() {
+                node.received.hash(&mut hasher);
+            }
+            hasher.finish()
+        };
+        // every message is pending, delivered or dropped, and replica 1 and 2 can receive their 2
+        // messages in 2 orders, so 3 * (3^2 + 1)^2 states
+        assert_eq!(
+            explore(
+                new_simulate,
+                node_fingerprint,
+                |_| true,
+                usize::MAX,
+                true,
+                false
+            ),
+            Ok(300)
+        );
+
+        let result = explore(
+            new_simulate,
+            node_fingerprint,
+            |simulate| simulate.nodes[&TestReplica(1)].received != [TestReplica(0)],
+            usize::MAX,
+            false,
+            false,
+        );
+        let Err(choices) = result else { panic!() };
+        let mut simulate = new_simulate();
+        for choice in choices {
+            assert!(matches!(choice, Choice::Deliver(_)));
+            simulate.choose(choice);
+        }
+        assert_eq!(simulate.nodes[&TestReplica(1)].received, [TestReplica(0)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "random faults are not explored")]
+    fn random_fault_exploration() {
+        let _ = explore(
+            || {
+                let mut simulate = flood(Simulate::default());
+                simulate.set_fault(Fault {
+                    drop: 0.5,
+                    ..Default::default()
+                });
+                simulate.init();
+                simulate
+            },
+            |_| 0,
+            |_| true,
+            usize::MAX,
+            false,
+            false,
+        );
+    }
+
+    #[derive(Default)]
+    struct Alarm(Vec<u32>);
+
+    impl Protocol<NodeEvent<()>> for Alarm {
+        type Effect = Vec<NodeEffect<()>>;
+
+        fn update(&mut self, event: NodeEvent<()>) -> Self::Effect {
+            match event {
+                NodeEvent::Init => vec![
+                    NodeEffect::SetTimer(0, Duration::from_millis(25)),
+                    NodeEffect::SetTimer(1, Duration::from_millis(15)),
+                    NodeEffect::SetTimer(2, Duration::from_millis(5)),
+                ],
+                NodeEvent::Timeout(id) => {
+                    self.0.push(id);
+                    if id == 2 {
+                        vec![NodeEffect::CancelTimer(1)]
+                    } else {
+                        Vec::new()
+                    }
+                }
+                _ => Vec::new(),
+            }
+        }
+    }
+
+    #[test]
+    fn timer_exploration() {
+        let new_simulate = || {
+            let mut simulate = Simulate::default();
+            simulate.nodes.insert(TestReplica(0), Alarm::default());
+            simulate.init();
+            simulate
+        };
+        let node_fingerprint = |simulate: &Simulate<Alarm, ()>| {
+            let mut hasher = DefaultHasher::new();
+            simulate.nodes[&TestReplica(0)].0.hash(&mut hasher);
+            hasher.finish()
+        };
+        // timers fire in any order regardless of their timeout time, except that 1 is canceled by
+        // 2, so 1 + 3 + 5 + 3 states, and ticking is a no-op
+        let fired_in_order = |simulate: &Simulate<Alarm, ()>| {
+            let fired = &simulate.nodes[&TestReplica(0)].0;
+            let position = |id| fired.iter().position(|&fired_id| fired_id == id);
+            position(2).is_none() || position(1) < position(2)
+        };
+        assert_eq!(
+            explore(
+                new_simulate,
+                node_fingerprint,
+                fired_in_order,
+                usize::MAX,
+                false,
+                true
+            ),
+            Ok(12)
+        );
+    }
+
+    #[test]
+    fn timer() {
+        let mut simulate = Simulate::default();
+        simulate.nodes.insert(TestReplica(0), Alarm::default());
+        simulate.init();
+        simulate.run_until(Duration::from_millis(20));
+        assert_eq!(simulate.nodes[&TestReplica(0)].0, [2]);
+        simulate.run_until(Duration::from_millis(30));
+        assert_eq!(simulate.nodes[&TestReplica(0)].0, [2, 0]);
+    }
+}
@@ -0,0 +1,463 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    io::{ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket},
+    os::fd::AsRawFd,
+    panic::panic_any,
+    thread::spawn,
+    time::{Duration, Instant},
+};
+
+use crossbeam::channel;
+
+use nix::{
+    errno::Errno,
+    poll::{ppoll, PollFd, PollFlags},
+    sys::{signal::SigSet, time::TimeSpec},
+};
+
+use crate::{protocol::Generate, Protocol};
+
+// the framing is the only difference from `udp`, so the events and the (de)serialization stages
+// are shared, and switching transport only means switching `Rx` and `Tx`
+pub use crate::udp::{Deserialize, RxEvent, RxEventOwned, Serialize, TxEvent};
+
+// every frame is prefixed with its length as little endian u32
+// larger frames are considered malicious, and the connection is closed
+pub const MAX_FRAME_SIZE: usize = 64 << 20;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(100);
+
+// further connections are left in the backlog until some are closed
+const MAX_CONNECTION_COUNT: usize = 1024;
+// reading from a connection is resumed in the next poll round after this many reads, so a fast
+// sender cannot starve the others
+const MAX_READ_COUNT: usize = 16;
+// accepting is paused for this long on errors other than running out of pending connections,
+// e.g., running out of file descriptors, which would wake up the poll again immediately
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+// the listener that is reachable from `remote`, similar to `udp::client_socket`
+pub fn client_listener(remote: impl ToSocketAddrs) -> TcpListener {
+    let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+    socket.connect(remote).unwrap();
+    TcpListener::bind((socket.local_addr().unwrap().ip(), 0)).unwrap()
+}
+
+// accept connections from remote `Tx`s and receive from all of them
+pub struct Rx(pub TcpListener);
+
+impl Generate for Rx {
+    type Event<'a> = RxEvent<'a>;
+
+    fn deploy<P>(&mut self, protocol: &mut P)
+    where
+        P: for<'a> Protocol<Self::Event<'a>>,
+    {
+        self.0.set_nonblocking(true).unwrap();
+        let mut connections = Vec::<(TcpStream, Vec<u8>)>::new();
+        let mut chunk = vec![0; 65536];
+        let mut accept_at = Instant::now();
+        let mut error_count = 0u64;
+        loop {
+            let now = Instant::now();
+            let accepting = connections.len() < MAX_CONNECTION_COUNT && now >= accept_at;
+            let mut fds = accepting
+                .then_some(self.0.as_raw_fd())
+                .into_iter()
+                .chain(connections.iter().map(|(stream, _)| stream.as_raw_fd()))
+                .map(|fd| PollFd::new(fd, PollFlags::POLLIN))
+                .collect::<Vec<_>>();
+            let timeout = (now < accept_at).then(|| TimeSpec::from_duration(accept_at - now));
+            match ppoll(&mut fds, timeout, Some(SigSet::empty())) {
+                Err(Errno::EINTR) => break,
+                Err(err) => panic_any(err),
+                Ok(_) => {}
+            }
+            while accepting && connections.len() < MAX_CONNECTION_COUNT {
+                match self.0.accept() {
+                    Ok((stream, _)) => {
+                        stream.set_nonblocking(true).unwrap();
+                        connections.push((stream, Vec::new()))
+                    }
+                    Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                    Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                    Err(err) => {
+                        error_count += 1;
+                        if error_count.is_power_of_two() {
+                            eprintln!("{error_count} accept error(s): {err}");
+                        }
+                        accept_at = Instant::now() + ACCEPT_BACKOFF;
+                        break;
+                    }
+                }
+            }
+            connections.retain_mut(|(stream, buf)| receive(stream, buf, &mut chunk, protocol));
+        }
+    }
+}
+
+// read from the stream up to `MAX_READ_COUNT` times, and deliver the complete frames after every
+// read, so an oversized frame is rejected as soon as its length arrives instead of being buffered
+// return false if the connection should be closed
+fn receive<P>(stream: &mut TcpStream, buf: &mut Vec<u8>, chunk: &mut [u8], protocol: &mut P) -> bool
+where
+    P: for<'a> Protocol<RxEvent<'a>>,
+{
+    for _ in 0..MAX_READ_COUNT {
+        match stream.read(chunk) {
+            Ok(0) => return false,
+            Ok(len) => buf.extend_from_slice(&chunk[..len]),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => return false,
+        }
+        if !deliver(buf, protocol) {
+            return false;
+        }
+    }
+    true
+}
+
+// deliver and remove all complete frames in `buf`
+// return false if an oversized frame is prefixed
+fn deliver<P>(buf: &mut Vec<u8>, protocol: &mut P) -> bool
+where
+    P: for<'a> Protocol<RxEvent<'a>>,
+{
+    let mut offset = 0;
+    while buf.len() - offset >= 4 {
+        let len = u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap()) as usize;
+        if len > MAX_FRAME_SIZE {
+            eprintln!("close connection for frame of {len} bytes");
+            return false;
+        }
+        if buf.len() - offset - 4 < len {
+            break;
+        }
+        protocol.update(RxEvent::Receive(Cow::Borrowed(
+            &buf[offset + 4..offset + 4 + len],
+        )));
+        offset += 4 + len;
+    }
+    buf.drain(..offset);
+    true
+}
+
+// keep one outgoing connection per remote address, each written by its own thread, so that
+// neither connecting nor writing to a slow or down remote blocks the others or the caller
+// the frames are queued up to `QUEUE_SIZE` per remote, and the overflowed ones are lost just like
+// the ones overflowing a UDP socket buffer
+pub struct Tx {
+    connections: HashMap<SocketAddr, channel::Sender<Box<[u8]>>>,
+    broadcast: Box<[SocketAddr]>,
+    pub drop_count: u64,
+}
+
+const QUEUE_SIZE: usize = 1024;
+
+impl Tx {
+    pub fn new(broadcast: Box<[SocketAddr]>) -> Self {
+        Self {
+            connections: Default::default(),
+            broadcast,
+            drop_count: 0,
+        }
+    }
+
+    fn send(&mut self, addr: SocketAddr, buf: &[u8]) {
+        // the remote would close the connection for it, and lose the queued frames as well
+        if buf.len() > MAX_FRAME_SIZE {
+            self.count_drop(addr);
+            return;
+        }
+        let mut frame = Vec::with_capacity(4 + buf.len());
+        frame.extend_from_slice(&(buf.len() as u32).to_le_bytes());
+        frame.extend_from_slice(buf);
+
+        let connection = self.connections.entry(addr).or_insert_with(|| {
+            let (sender, receiver) = channel::bounded::<Box<[u8]>>(QUEUE_SIZE);
+            let mut connection = Connection::new(addr);
+            // exits after the queued frames are written once `Tx` is dropped
+            spawn(move || {
+                for frame in receiver {
+                    connection.send(&frame)
+                }
+            });
+            sender
+        });
+        if connection.try_send(frame.into()).is_err() {
+            self.count_drop(addr)
+        }
+    }
+
+    fn count_drop(&mut self, addr: SocketAddr) {
+        self.drop_count += 1;
+        // log less as more are dropped, in case the remote is down for long
+        if self.drop_count.is_power_of_two() {
+            eprintln!("dropped {} frame(s) to {addr}", self.drop_count);
+        }
+    }
+}
+
+struct Connection {
+    addr: SocketAddr,
+    stream: Option<TcpStream>,
+    backoff: Duration,
+    // connecting is not retried before this, and the frames in between are lost, so a down remote
+    // does not stall the thread with a connecting timeout for every frame
+    retry_at: Instant,
+}
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+impl Connection {
+    fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            stream: None,
+            backoff: INITIAL_BACKOFF,
+            retry_at: Instant::now(),
+        }
+    }
+
+    fn connect(&mut self) -> Option<&mut TcpStream> {
+        if self.stream.is_none() {
+            if Instant::now() < self.retry_at {
+                return None;
+            }
+            match TcpStream::connect_timeout(&self.addr, CONNECT_TIMEOUT) {
+                Ok(stream) => {
+                    stream.set_nodelay(true).unwrap();
+                    self.stream = Some(stream);
+                    self.backoff = INITIAL_BACKOFF;
+                }
+                Err(err) => {
+                    eprintln!(
+                        "connect {}: {err}, retry after {:?}",
+                        self.addr, self.backoff
+                    );
+                    self.retry_at = Instant::now() + self.backoff;
+                    self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+                    return None;
+                }
+            }
+        }
+        self.stream.as_mut()
+    }
+
+    fn send(&mut self, frame: &[u8]) {
+        // the connection may have been closed by the remote, in which case retry once with a fresh
+        // connection
+        for _ in 0..2 {
+            // the frame is lost, just like to an unreachable UDP destination
+            let Some(stream) = self.connect() else {
+                return;
+            };
+            if stream.write_all(frame).is_ok() {
+                return;
+            }
+            self.stream = None;
+        }
+        eprintln!("send to {} failed", self.addr);
+    }
+}
+
+impl Protocol<TxEvent> for Tx {
+    type Effect = ();
+
+    fn update(&mut self, event: TxEvent) -> Self::Effect {
+        match event {
+            TxEvent::Send(addr, buf) => self.send(addr, &buf),
+            TxEvent::Broadcast(buf) => {
+                for i in 0..self.broadcast.len() {
+                    self.send(self.broadcast[i], &buf)
+                }
+            }
+        }
+    }
+}
+
+impl Protocol<Vec<TxEvent>> for Tx {
+    type Effect = ();
+
+    fn update(&mut self, events: Vec<TxEvent>) -> Self::Effect {
+        for event in events {
+            self.update(event)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        thread::sleep,
+        time::{Duration, Instant},
+    };
+
+    use crate::Protocol;
+
+    use super::{
+        receive, RxEvent, Tx, TxEvent, CONNECT_TIMEOUT, INITIAL_BACKOFF, MAX_FRAME_SIZE,
+        MAX_READ_COUNT,
+    };
+
+    fn read_frame(stream: &mut TcpStream) -> Vec<u8> {
+        let mut len = [0; 4];
+        stream.read_exact(&mut len).unwrap();
+        let mut buf = vec![0; u32::from_le_bytes(len) as usize];
+        stream.read_exact(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn framing() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut tx = Tx::new(Default::default());
+        let addr = listener.local_addr().unwrap();
+        // larger than any UDP datagram
+        let large = vec![42; 100000];
+        tx.update(vec![
+            TxEvent::Send(addr, b"hello".to_vec().into()),
+            TxEvent::Send(addr, large.clone().into()),
+        ]);
+
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        let mut frames = Vec::new();
+        let (mut buf, mut chunk) = (Vec::new(), vec![0; 1024]);
+        while frames.len() < 2 {
+            assert!(receive(
+                &mut stream,
+                &mut buf,
+                &mut chunk,
+                &mut |RxEvent::Receive(frame): RxEvent<'_>| frames.push(frame.into_owned()),
+            ));
+        }
+        assert_eq!(frames, [b"hello".to_vec(), large]);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn read_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut remote = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        let frame = [&1000u32.to_le_bytes()[..], &[42; 1000]].concat();
+        for _ in 0..100 {
+            remote.write_all(&frame).unwrap();
+        }
+        // for all frames to be buffered on the receiving side
+        sleep(Duration::from_millis(10));
+
+        let mut count = 0;
+        let (mut buf, mut chunk) = (Vec::new(), vec![0; 1024]);
+        let mut receive = |buf: &mut Vec<u8>, chunk: &mut Vec<u8>, count: &mut usize| {
+            assert!(receive(
+                &mut stream,
+                buf,
+                chunk,
+                &mut |RxEvent::Receive(frame): RxEvent<'_>| {
+                    assert_eq!(frame.len(), 1000);
+                    *count += 1
+                },
+            ))
+        };
+        receive(&mut buf, &mut chunk, &mut count);
+        // the rest is left for the following poll rounds
+        assert!(count <= MAX_READ_COUNT);
+        while count < 100 {
+            receive(&mut buf, &mut chunk, &mut count);
+        }
+    }
+
+    #[test]
+    fn oversized_frame() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut remote = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        // closed by the length alone, without waiting for the frame
+        remote
+            .write_all(&(MAX_FRAME_SIZE as u32 + 1).to_le_bytes())
+            .unwrap();
+        sleep(Duration::from_millis(10));
+        let (mut buf, mut chunk) = (Vec::new(), vec![0; 1024]);
+        assert!(!receive(
+            &mut stream,
+            &mut buf,
+            &mut chunk,
+            &mut |_: RxEvent<'_>| unreachable!(),
+        ));
+
+        // and never sent in the first place
+        let mut tx = Tx::new(Default::default());
+        let addr = listener.local_addr().unwrap();
+        tx.update(vec![
+            TxEvent::Send(addr, b"hello".to_vec().into()),
+            TxEvent::Send(addr, vec![0; MAX_FRAME_SIZE + 1].into()),
+        ]);
+        assert_eq!(tx.drop_count, 1);
+        let (mut stream, _) = listener.accept().unwrap();
+        assert_eq!(read_frame(&mut stream), b"hello");
+    }
+
+    #[test]
+    fn reconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut tx = Tx::new([addr].into());
+        tx.update(TxEvent::Broadcast(b"hello".to_vec().into()));
+        let (mut stream, _) = listener.accept().unwrap();
+        assert_eq!(read_frame(&mut stream), b"hello");
+        drop(stream);
+
+        // the first sends may still succeed on the closed connection and get lost, the following
+        // ones go through a new connection
+        listener.set_nonblocking(true).unwrap();
+        let mut stream = loop {
+            tx.update(TxEvent::Broadcast(b"world".to_vec().into()));
+            if let Ok((stream, _)) = listener.accept() {
+                break stream;
+            }
+        };
+        stream.set_nonblocking(false).unwrap();
+        assert_eq!(read_frame(&mut stream), b"world");
+    }
+
+    #[test]
+    fn down_remote() {
+        // the port is free after the listener is dropped, so connecting is refused
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let mut tx = Tx::new([addr].into());
+        let start = Instant::now();
+        for _ in 0..100 {
+            tx.update(TxEvent::Broadcast(b"lost".to_vec().into()));
+        }
+        // the caller is never blocked by connecting
+        assert!(start.elapsed() < CONNECT_TIMEOUT);
+        // for the writing thread to be refused and drop the frames, well before retrying
+        sleep(INITIAL_BACKOFF / 2);
+
+        // connected again after the backoff, and the frames in between are lost
+        let listener = TcpListener::bind(addr).unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let mut stream = loop {
+            tx.update(TxEvent::Broadcast(b"hello".to_vec().into()));
+            if let Ok((stream, _)) = listener.accept() {
+                break stream;
+            }
+            sleep(Duration::from_millis(10));
+        };
+        stream.set_nonblocking(false).unwrap();
+        assert_eq!(read_frame(&mut stream), b"hello");
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    marker::PhantomData,
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{protocol::Generate, Protocol};
+
+// write every event to the trace before passing it to the node
+// wrap the client inside `Workload` to also record the ops
+// the trace is flushed after every event, so it is complete up to the last event even if the
+// process is aborted
+pub struct Record<P, W> {
+    pub node: P,
+    writer: W,
+}
+
+impl<P, W> Record<P, W> {
+    pub fn new(node: P, writer: W) -> Self {
+        Self { node, writer }
+    }
+
+    // write `header` ahead of the events, e.g. the arguments the node is constructed with, which
+    // are read by `Replay::header` to construct the same node
+    pub fn with_header(node: P, mut writer: W, header: &impl Serialize) -> Self
+    where
+        W: Write,
+    {
+        bincode::serialize_into(&mut writer, header).unwrap();
+        writer.flush().unwrap();
+        Self { node, writer }
+    }
+}
+
+impl<P, W, E> Protocol<E> for Record<P, W>
+where
+    P: Protocol<E>,
+    W: Write,
+    E: Serialize,
+{
+    type Effect = P::Effect;
+
+    fn update(&mut self, event: E) -> Self::Effect {
+        bincode::serialize_into(&mut self.writer, &event).unwrap();
+        self.writer.flush().unwrap();
+        self.node.update(event)
+    }
+}
+
+// feed the recorded events to a fresh node, which should go through the same state transitions
+// if the node is deterministic (besides e.g. random client id, which should be constructed with
+// the recorded header)
+pub struct Replay<R, E> {
+    reader: R,
+    event: PhantomData<E>,
+}
+
+impl<R, E> Replay<R, E> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            event: PhantomData,
+        }
+    }
+
+    // must be called before deploying if the trace is recorded with `Record::with_header`
+    pub fn header<H>(&mut self) -> H
+    where
+        R: Read,
+        H: DeserializeOwned,
+    {
+        bincode::deserialize_from(&mut self.reader).unwrap()
+    }
+}
+
+impl<R, E> Generate for Replay<R, E>
+where
+    R: Read,
+    E: DeserializeOwned,
+{
+    type Event<'a> = E;
+
+    fn deploy<P>(&mut self, protocol: &mut P)
+    where
+        P: for<'a> Protocol<Self::Event<'a>, Effect = ()>,
+    {
+        loop {
+            match bincode::deserialize_from(&mut self.reader) {
+                Ok(event) => protocol.update(event),
+                Err(err) if matches!(&*err, bincode::ErrorKind::Io(err) if err.kind() == ErrorKind::UnexpectedEof) => {
+                    break
+                }
+                Err(err) => panic!("{err}"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc, time::Duration};
+
+    use crate::{
+        app,
+        node::{ClientEffect, ClientEvent, Workload},
+        protocol::{Generate, OneOf},
+        simulate::{
+            cluster::{self, assert_results},
+            Fault,
+        },
+        unreplicated::{Client, Message, Replica},
+        vr, App,
+        NodeAddr::{TestClient, TestReplica},
+        NodeEvent, Protocol, Simulate,
+    };
+
+    use super::{Record, Replay};
+
+    #[test]
+    fn record_replay() {
+        let mut simulate = Simulate::<_, Message>::default();
+        simulate.nodes.insert(
+            TestClient(0),
+            OneOf::A(Workload::new_test(
+                Record::with_header(
+                    Client::new(42, TestClient(0), TestReplica(0)),
+                    Vec::new(),
+                    &(42u32, TestClient(0), TestReplica(0)),
+                ),
+                (0..10).map(|i| format!("op {i}").into_bytes()),
+            )),
+        );
+        simulate.nodes.insert(
+            TestReplica(0),
+            OneOf::B(
+                Replica::new(App::Echo(app::Echo))
+                    .then(|effect: Option<_>| effect.into_iter().collect()),
+            ),
+        );
+        simulate.set_fault(Fault {
+            drop: 0.2,
+            ..Default::default()
+        });
+        simulate.init();
+        simulate.run_until(Duration::from_secs(1));
+
+        let OneOf::A(workload) = simulate.crash(TestClient(0)) else {
+            unreachable!()
+        };
+        assert_eq!(workload.results.len(), 10);
+        let mut replay = Replay::<_, ClientEvent<Message>>::new(&*workload.node.writer);
+        let (id, addr, replica_addr) = replay.header();
+        let mut client = Client::new(id, addr, replica_addr);
+        let mut results = Vec::new();
+        replay.deploy(
+            &mut Protocol::borrow_mut(&mut client).then(|effects: Vec<_>| {
+                for effect in effects {
+                    if let ClientEffect::Result(result) = effect {
+                        results.push(result)
+                    }
+                }
+            }),
+        );
+        assert_eq!(results, workload.results);
+        assert_eq!(client.resend_stats, workload.node.node.resend_stats);
+    }
+
+    #[test]
+    fn record_replay_replica() {
+        // the effects of every replica, to be compared with the replayed ones
+        let logs = (0..3)
+            .map(|_| Rc::new(RefCell::new(Vec::new())))
+            .collect::<Vec<_>>();
+        let mut simulate = cluster::new(
+            1,
+            3,
+            10,
+            |i, replicas| vr::Client::new(i, TestClient(i), replicas),
+            |i, replicas, app| {
+                let log = logs[i as usize].clone();
+                Record::with_header(
+                    vr::Replica::new(i, replicas.clone(), app).then(move |effects: Vec<_>| {
+                        log.borrow_mut().push(format!("{effects:?}"));
+                        effects
+                    }),
+                    Vec::new(),
+                    &(i, replicas),
+                )
+            },
+        );
+        simulate.set_fault(Fault {
+            drop: 0.2,
+            ..Default::default()
+        });
+        simulate.init();
+        simulate.run_until(Duration::from_secs(10));
+        assert_results(&simulate, 1, 10);
+
+        for (i, log) in logs.iter().enumerate() {
+            let OneOf::B(record) = simulate.crash(TestReplica(i as _)) else {
+                unreachable!()
+            };
+            let mut replay = Replay::<_, NodeEvent<vr::Message>>::new(&*record.writer);
+            let (id, replicas) = replay.header();
+            let mut replica = vr::Replica::new(id, replicas, App::Echo(app::Echo));
+            let mut replayed = Vec::new();
+            replay.deploy(
+                &mut Protocol::borrow_mut(&mut replica)
+                    .then(|effects: Vec<_>| replayed.push(format!("{effects:?}"))),
+            );
+            assert_eq!(replayed, *log.borrow());
+        }
+    }
+}
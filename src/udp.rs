@@ -1,10 +1,13 @@
 use std::{
     borrow::Cow,
+    collections::HashMap,
+    io::{IoSlice, IoSliceMut},
     marker::PhantomData,
     net::{SocketAddr, ToSocketAddrs, UdpSocket},
-    os::fd::AsRawFd,
+    os::fd::{AsRawFd, FromRawFd},
     panic::panic_any,
     sync::Arc,
+    time::{Duration, Instant},
 };
 
 use bincode::Options;
@@ -12,11 +15,18 @@ use bincode::Options;
 use nix::{
     errno::Errno,
     poll::{ppoll, PollFd, PollFlags},
-    sys::signal::SigSet,
+    sys::{
+        signal::SigSet,
+        socket::{
+            bind, recvmmsg, sendmmsg, setsockopt, socket, sockopt::ReusePort, AddressFamily,
+            ControlMessage, MsgFlags, MultiHeaders, SockFlag, SockType, SockaddrStorage,
+        },
+    },
 };
+use rand::random;
 use serde::de::DeserializeOwned;
 
-use crate::{protocol::Generate, NodeAddr, NodeEffect, NodeEvent, Protocol};
+use crate::{node::UnhandledTimer, protocol::Generate, NodeAddr, NodeEffect, NodeEvent, Protocol};
 
 // really seek for a better way
 pub fn client_socket(remote: impl ToSocketAddrs) -> UdpSocket {
@@ -27,12 +37,32 @@ pub fn client_socket(remote: impl ToSocketAddrs) -> UdpSocket {
     UdpSocket::bind(addr).unwrap()
 }
 
+// `num` sockets bound to the same address with `SO_REUSEPORT`, the kernel distributes incoming
+// datagrams among them by hashing the remote address, so one `Rx` can be deployed per socket
+pub fn reuse_port_sockets(addr: SocketAddr, num: usize) -> Vec<UdpSocket> {
+    let family = if addr.is_ipv4() {
+        AddressFamily::Inet
+    } else {
+        AddressFamily::Inet6
+    };
+    (0..num)
+        .map(|_| {
+            let fd = socket(family, SockType::Datagram, SockFlag::empty(), None).unwrap();
+            // take the ownership first, so the fd is closed if anything below panics
+            let socket = unsafe { UdpSocket::from_raw_fd(fd) };
+            setsockopt(fd, ReusePort, &true).unwrap();
+            bind(fd, &SockaddrStorage::from(addr)).unwrap();
+            socket
+        })
+        .collect()
+}
+
 pub fn init_socket(socket: &UdpSocket) {
     socket.set_nonblocking(true).unwrap();
     ppoll(
         &mut [PollFd::new(
             socket.as_raw_fd(),
-            PollFlags::from_iter([PollFlags::POLLOUT].into_iter()),
+            PollFlags::from_iter([PollFlags::POLLOUT]),
         )],
         None,
         None,
@@ -61,8 +91,145 @@ impl From<RxEventOwned> for RxEvent<'static> {
     }
 }
 
+// the max number of datagrams received or sent with one `recvmmsg`/`sendmmsg`
+pub const BATCH_SIZE: usize = 32;
+
+// every datagram sent by `Tx` starts with one of these tags
+// a message that does not fit into one datagram is split into fragments, each of which carries a
+// header of (random message id: u64, index: u16, count: u16) in little endian after the tag
+const WHOLE: u8 = 0;
+const FRAGMENT: u8 = 1;
+const FRAGMENT_HEADER_SIZE: usize = 13;
+
+// the max UDP payload over IPv4
+pub const MAX_DATAGRAM_SIZE: usize = 65507;
+// larger messages are dropped by receivers, to bound the memory of reassembly
+pub const MAX_MESSAGE_SIZE: usize = 1024 * (MAX_DATAGRAM_SIZE - FRAGMENT_HEADER_SIZE);
+// the fragments of a message that are not all received within this duration are discarded
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(1);
+// the limits of the messages under reassembly, beyond which new fragments are dropped, so forged
+// fragments cannot exhaust the memory
+const MAX_REASSEMBLY_COUNT: usize = 256;
+const MAX_REASSEMBLY_SIZE: usize = 2 * MAX_MESSAGE_SIZE;
+
+// strip the tags of the datagrams and reassemble the fragments, shared by the receivers of the
+// datagrams sent by `Tx`
+pub struct Reassemble {
+    messages: HashMap<u64, Fragments>,
+    timeout: Duration,
+    // of all fragments in `messages`
+    size: usize,
+    max_size: usize,
+}
+
+struct Fragments {
+    // of the first received fragment
+    arrival: Instant,
+    count: usize,
+    // index => payload, sparse so that the memory is bounded by the received fragments instead of
+    // the claimed count
+    fragments: HashMap<usize, Box<[u8]>>,
+    size: usize,
+}
+
+impl Default for Reassemble {
+    fn default() -> Self {
+        Self {
+            messages: Default::default(),
+            timeout: REASSEMBLY_TIMEOUT,
+            size: 0,
+            max_size: MAX_REASSEMBLY_SIZE,
+        }
+    }
+}
+
+impl Reassemble {
+    pub fn receive<P>(&mut self, datagram: &[u8], protocol: &mut P)
+    where
+        P: for<'a> Protocol<RxEvent<'a>>,
+    {
+        match datagram.split_first() {
+            Some((&WHOLE, message)) => {
+                protocol.update(RxEvent::Receive(Cow::Borrowed(message)));
+            }
+            // every fragment carries a nonempty part of the message
+            Some((&FRAGMENT, fragment)) if fragment.len() > FRAGMENT_HEADER_SIZE - 1 => {
+                let id = u64::from_le_bytes(fragment[..8].try_into().unwrap());
+                let index = u16::from_le_bytes(fragment[8..10].try_into().unwrap()) as usize;
+                let count = u16::from_le_bytes(fragment[10..12].try_into().unwrap()) as usize;
+                if let Some(message) = self.insert(id, index, count, &fragment[12..]) {
+                    protocol.update(RxEvent::Receive(Cow::Borrowed(&message)));
+                }
+            }
+            // stray datagram, dropped
+            _ => {}
+        }
+    }
+
+    fn insert(&mut self, id: u64, index: usize, count: usize, fragment: &[u8]) -> Option<Vec<u8>> {
+        let now = Instant::now();
+        let timeout = self.timeout;
+        let mut expired_size = 0;
+        self.messages.retain(|_, fragments| {
+            let expired = now - fragments.arrival >= timeout;
+            if expired {
+                expired_size += fragments.size;
+            }
+            !expired
+        });
+        self.size -= expired_size;
+
+        if index >= count || count * (MAX_DATAGRAM_SIZE - FRAGMENT_HEADER_SIZE) > MAX_MESSAGE_SIZE {
+            return None;
+        }
+        match self.messages.get(&id) {
+            // mismatched count is forged or a random id collision, either way cannot be reassembled
+            Some(message) if message.count != count => return None,
+            Some(message) if message.fragments.contains_key(&index) => return None,
+            Some(_) => {}
+            None if self.messages.len() >= MAX_REASSEMBLY_COUNT => return None,
+            None => {}
+        }
+        if self.size + fragment.len() > self.max_size {
+            return None;
+        }
+        let message = self.messages.entry(id).or_insert_with(|| Fragments {
+            arrival: now,
+            count,
+            fragments: Default::default(),
+            size: 0,
+        });
+        message.fragments.insert(index, fragment.into());
+        message.size += fragment.len();
+        self.size += fragment.len();
+        if message.fragments.len() < count {
+            return None;
+        }
+        let mut message = self.messages.remove(&id).unwrap();
+        self.size -= message.size;
+        Some(
+            (0..count)
+                .flat_map(|index| message.fragments.remove(&index).unwrap().into_vec())
+                .collect(),
+        )
+    }
+}
+
 pub struct Rx(pub Arc<UdpSocket>);
 
+// a pending ICMP error of previous sending, or a transient shortage, which does not affect the
+// following datagrams
+pub(crate) fn is_transient(err: Errno) -> bool {
+    matches!(
+        err,
+        Errno::ECONNREFUSED
+            | Errno::EHOSTUNREACH
+            | Errno::ENETUNREACH
+            | Errno::ENOMEM
+            | Errno::ENOBUFS
+    )
+}
+
 impl Generate for Rx {
     type Event<'a> = RxEvent<'a>;
 
@@ -70,7 +237,10 @@ impl Generate for Rx {
     where
         P: for<'a> Protocol<Self::Event<'a>>,
     {
-        let mut buf = [0; 65507];
+        let mut bufs = vec![[0; MAX_DATAGRAM_SIZE]; BATCH_SIZE];
+        let mut headers = MultiHeaders::<SockaddrStorage>::preallocate(BATCH_SIZE, None);
+        let mut reassemble = Reassemble::default();
+        let mut error_count = 0u64;
         loop {
             match ppoll(
                 &mut [PollFd::new(self.0.as_raw_fd(), PollFlags::POLLIN)],
@@ -79,11 +249,38 @@ impl Generate for Rx {
             ) {
                 Err(Errno::EINTR) => break,
                 Err(err) => panic_any(err),
-                Ok(_) => {
-                    while let Ok((len, _)) = self.0.recv_from(&mut buf) {
-                        protocol.update(RxEvent::Receive(Cow::Borrowed(&buf[..len])));
+                Ok(_) => loop {
+                    let lens = {
+                        let iovs = bufs
+                            .iter_mut()
+                            .map(|buf| [IoSliceMut::new(buf)])
+                            .collect::<Vec<_>>();
+                        match recvmmsg(
+                            self.0.as_raw_fd(),
+                            &mut headers,
+                            &iovs,
+                            MsgFlags::empty(),
+                            None,
+                        ) {
+                            Ok(messages) => {
+                                messages.map(|message| message.bytes).collect::<Vec<_>>()
+                            }
+                            Err(Errno::EAGAIN) => break,
+                            Err(err) if is_transient(err) => {
+                                error_count += 1;
+                                // log less as more occur, in case of flooding
+                                if error_count.is_power_of_two() {
+                                    eprintln!("{error_count} receive error(s): {err}");
+                                }
+                                continue;
+                            }
+                            Err(err) => panic_any(err),
+                        }
+                    };
+                    for (buf, len) in bufs.iter().zip(lens) {
+                        reassemble.receive(&buf[..len], protocol);
                     }
-                }
+                },
             }
         }
     }
@@ -97,11 +294,96 @@ pub enum TxEvent {
 pub struct Tx {
     socket: Arc<UdpSocket>,
     broadcast: Box<[SocketAddr]>,
+    pub error_count: u64,
 }
 
 impl Tx {
     pub fn new(socket: Arc<UdpSocket>, broadcast: Box<[SocketAddr]>) -> Self {
-        Self { socket, broadcast }
+        Self {
+            socket,
+            broadcast,
+            error_count: 0,
+        }
+    }
+
+    fn send_batch(&mut self, messages: &[(&[u8], SocketAddr)]) {
+        // (fragment header index, payload, destination), messages that fit are sent as is after the
+        // `WHOLE` tag
+        let mut fragment_headers = Vec::new();
+        let mut datagrams = Vec::new();
+        for &(buf, addr) in messages {
+            if buf.len() < MAX_DATAGRAM_SIZE {
+                datagrams.push((None, buf, addr));
+                continue;
+            }
+            // would be dropped by the receiver anyway
+            if buf.len() > MAX_MESSAGE_SIZE {
+                eprintln!("dropped message of {} bytes to {addr}", buf.len());
+                continue;
+            }
+            let id = random::<u64>();
+            let fragments = buf.chunks(MAX_DATAGRAM_SIZE - FRAGMENT_HEADER_SIZE);
+            let count = fragments.len() as u16;
+            for (index, fragment) in fragments.enumerate() {
+                let mut header = [FRAGMENT; FRAGMENT_HEADER_SIZE];
+                header[1..9].copy_from_slice(&id.to_le_bytes());
+                header[9..11].copy_from_slice(&(index as u16).to_le_bytes());
+                header[11..].copy_from_slice(&count.to_le_bytes());
+                datagrams.push((Some(fragment_headers.len()), fragment, addr));
+                fragment_headers.push(header);
+            }
+        }
+
+        // `MultiHeaders` is not `Send`, so it cannot be kept in `Tx` which is moved into threads
+        let mut headers =
+            MultiHeaders::<SockaddrStorage>::preallocate(datagrams.len().min(BATCH_SIZE), None);
+        for datagrams in datagrams.chunks(BATCH_SIZE) {
+            let iovs = datagrams
+                .iter()
+                .map(|&(header, buf, _)| {
+                    let header = header.map_or(&[WHOLE][..], |i| &fragment_headers[i]);
+                    [IoSlice::new(header), IoSlice::new(buf)]
+                })
+                .collect::<Vec<_>>();
+            let addrs = datagrams
+                .iter()
+                .map(|&(_, _, addr)| Some(SockaddrStorage::from(addr)))
+                .collect::<Vec<_>>();
+            let mut sent = 0;
+            // `sendmmsg` may return after sending a prefix of the batch
+            while sent < datagrams.len() {
+                match sendmmsg(
+                    self.socket.as_raw_fd(),
+                    &mut headers,
+                    &iovs[sent..],
+                    &addrs[sent..],
+                    [] as [ControlMessage; 0],
+                    MsgFlags::empty(),
+                ) {
+                    Ok(results) => sent += results.count(),
+                    // the send buffer of the non-blocking socket is full, e.g. by the fragments of
+                    // a large message, wait until it is drained
+                    Err(Errno::EAGAIN) => match ppoll(
+                        &mut [PollFd::new(self.socket.as_raw_fd(), PollFlags::POLLOUT)],
+                        None,
+                        None,
+                    ) {
+                        Ok(_) | Err(Errno::EINTR) => {}
+                        Err(err) => panic_any(err),
+                    },
+                    // the first datagram of the rest is not sent, skip it as if it is lost
+                    Err(err) if is_transient(err) => {
+                        sent += 1;
+                        self.error_count += 1;
+                        // log less as more occur, in case a remote is down for long
+                        if self.error_count.is_power_of_two() {
+                            eprintln!("{} send error(s): {err}", self.error_count);
+                        }
+                    }
+                    Err(err) => panic_any(err),
+                }
+            }
+        }
     }
 }
 
@@ -110,26 +392,52 @@ impl Protocol<TxEvent> for Tx {
 
     fn update(&mut self, event: TxEvent) -> Self::Effect {
         match event {
-            TxEvent::Send(addr, buf) => {
-                self.socket.send_to(&buf, addr).unwrap();
-            }
+            TxEvent::Send(addr, buf) => self.send_batch(&[(&buf, addr)]),
             TxEvent::Broadcast(buf) => {
-                for &addr in &*self.broadcast {
-                    self.socket.send_to(&buf, addr).unwrap();
+                let messages = self
+                    .broadcast
+                    .iter()
+                    .map(|&addr| (&*buf, addr))
+                    .collect::<Vec<_>>();
+                self.send_batch(&messages)
+            }
+        }
+    }
+}
+
+// batched version that sends everything with as few system calls as possible
+impl Protocol<Vec<TxEvent>> for Tx {
+    type Effect = ();
+
+    fn update(&mut self, events: Vec<TxEvent>) -> Self::Effect {
+        let mut messages = Vec::new();
+        for event in &events {
+            match event {
+                TxEvent::Send(addr, buf) => messages.push((&**buf, *addr)),
+                TxEvent::Broadcast(buf) => {
+                    messages.extend(self.broadcast.iter().map(|&addr| (&**buf, addr)))
                 }
             }
         }
+        self.send_batch(&messages)
     }
 }
 
 // these are not generic (de)serialization infrastration because they interact with `Rx/TxEvent`
 // if find a good way to generalize against those, they can be moved to `crate::node`
 
-pub struct Deserialize<M>(PhantomData<M>);
+// malformed datagrams, either stray or forged, are counted and dropped instead of panicking
+pub struct Deserialize<M> {
+    pub drop_count: u64,
+    _m: PhantomData<M>,
+}
 
 impl<M> Default for Deserialize<M> {
     fn default() -> Self {
-        Self(Default::default())
+        Self {
+            drop_count: 0,
+            _m: Default::default(),
+        }
     }
 }
 
@@ -137,16 +445,71 @@ impl<M> Protocol<RxEvent<'_>> for Deserialize<M>
 where
     M: DeserializeOwned,
 {
-    type Effect = NodeEvent<M>;
+    type Effect = Option<NodeEvent<M>>;
 
     fn update(&mut self, event: RxEvent) -> Self::Effect {
         let RxEvent::Receive(buf) = event;
-        NodeEvent::Handle(
-            bincode::options()
-                .allow_trailing_bytes()
-                .deserialize(&buf)
-                .unwrap(),
-        )
+        decode(&buf, &mut self.drop_count).map(NodeEvent::Handle)
+    }
+}
+
+// message types that borrow from the datagram, e.g. with `&'a [u8]` in place of `Box<[u8]>`
+// implemented by the `'static` instance of the type by convention
+pub trait BorrowMessage {
+    type Message<'a>: serde::Deserialize<'a>;
+}
+
+// the zero-copy version of `Deserialize`, which only works with borrowed `RxEvent`, so the node
+// must process in the `Rx` thread instead of behind a channel
+pub struct DeserializeBorrowed<M> {
+    pub drop_count: u64,
+    _m: PhantomData<M>,
+}
+
+impl<M> Default for DeserializeBorrowed<M> {
+    fn default() -> Self {
+        Self {
+            drop_count: 0,
+            _m: Default::default(),
+        }
+    }
+}
+
+impl<'a, M> Protocol<RxEvent<'a>> for DeserializeBorrowed<M>
+where
+    M: BorrowMessage,
+{
+    type Effect = Option<NodeEvent<M::Message<'a>>>;
+
+    fn update(&mut self, event: RxEvent<'a>) -> Self::Effect {
+        // owned event is only produced by crossing threads with `RxEventOwned`
+        let RxEvent::Receive(Cow::Borrowed(buf)) = event else {
+            panic!()
+        };
+        decode(buf, &mut self.drop_count).map(NodeEvent::Handle)
+    }
+}
+
+fn decode<'a, M>(buf: &'a [u8], drop_count: &mut u64) -> Option<M>
+where
+    M: serde::Deserialize<'a>,
+{
+    // a well-formed message never decodes to more bytes than the datagram carries, so a forged
+    // length prefix fails here instead of causing a large allocation
+    match bincode::options()
+        .with_limit(buf.len() as _)
+        .allow_trailing_bytes()
+        .deserialize(buf)
+    {
+        Ok(message) => Some(message),
+        Err(err) => {
+            *drop_count += 1;
+            // log less as more are dropped, in case of flooding
+            if drop_count.is_power_of_two() {
+                eprintln!("dropped {drop_count} malformed datagram(s): {err}");
+            }
+            None
+        }
     }
 }
 
@@ -162,10 +525,10 @@ impl<M> Protocol<NodeEffect<M>> for Serialize<M>
 where
     M: serde::Serialize,
 {
-    type Effect = TxEvent;
+    type Effect = Option<TxEvent>;
 
     fn update(&mut self, event: NodeEffect<M>) -> Self::Effect {
-        match event {
+        Some(match event {
             NodeEffect::Send(NodeAddr::Socket(addr), message) => {
                 let buf = bincode::options().serialize(&message).unwrap().into();
                 TxEvent::Send(addr, buf)
@@ -175,6 +538,320 @@ where
                 let buf = bincode::options().serialize(&message).unwrap().into();
                 TxEvent::Broadcast(buf)
             }
+            // should be intercepted by `node::Timer`
+            NodeEffect::SetTimer(id, _) | NodeEffect::CancelTimer(id) => {
+                UnhandledTimer(id).report();
+                return None;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        borrow::Cow,
+        net::UdpSocket,
+        os::{fd::AsRawFd, unix::thread::JoinHandleExt},
+        sync::Arc,
+        thread::{sleep, spawn},
+        time::Duration,
+    };
+
+    use bincode::Options;
+    use crossbeam::channel;
+    use nix::sys::{
+        pthread::pthread_kill,
+        signal::Signal,
+        socket::{getsockopt, setsockopt, sockopt::RcvBuf},
+    };
+
+    use crate::{capture_stop, protocol::Generate, NodeEffect, NodeEvent, Protocol};
+
+    use super::{
+        init_socket, reuse_port_sockets, Deserialize, Reassemble, Rx, RxEvent, Serialize, Tx,
+        TxEvent, BATCH_SIZE, FRAGMENT, FRAGMENT_HEADER_SIZE, MAX_DATAGRAM_SIZE,
+        MAX_REASSEMBLY_COUNT, WHOLE,
+    };
+
+    #[test]
+    fn batched_send() {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+        let receivers = (0..3)
+            .map(|_| UdpSocket::bind("127.0.0.1:0").unwrap())
+            .collect::<Vec<_>>();
+        let addrs = receivers
+            .iter()
+            .map(|receiver| receiver.local_addr().unwrap())
+            .collect::<Vec<_>>();
+        let mut tx = Tx::new(socket, addrs[1..].into());
+        // more than `BATCH_SIZE` messages in total
+        let mut events = (0..40u8)
+            .map(|i| TxEvent::Send(addrs[0], [i].into()))
+            .collect::<Vec<_>>();
+        events.push(TxEvent::Broadcast([255].into()));
+        tx.update(events);
+
+        // with the leading tag
+        let mut buf = [0; 2];
+        for i in 0..40 {
+            assert_eq!(receivers[0].recv(&mut buf).unwrap(), 2);
+            assert_eq!(buf, [WHOLE, i]);
+        }
+        for receiver in &receivers[1..] {
+            assert_eq!(receiver.recv(&mut buf).unwrap(), 2);
+            assert_eq!(buf, [WHOLE, 255]);
+        }
+    }
+
+    #[test]
+    fn reuse_port() {
+        // bind on an unspecified port gives every socket a different one, so pick one first
+        let addr = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let sockets = reuse_port_sockets(addr, 4);
+        for socket in &sockets {
+            assert_eq!(socket.local_addr().unwrap(), addr);
+        }
+
+        // datagrams from the same remote address always land in the same socket
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for i in 0..10u8 {
+            sender.send_to(&[i], addr).unwrap();
         }
+        let receiver = sockets
+            .iter()
+            .find(|socket| {
+                socket.set_nonblocking(true).unwrap();
+                socket.peek(&mut [0]).is_ok()
+            })
+            .unwrap();
+        receiver.set_nonblocking(false).unwrap();
+        let mut buf = [0; 1];
+        for i in 0..10 {
+            receiver.recv(&mut buf).unwrap();
+            assert_eq!(buf[0], i);
+        }
+    }
+
+    #[test]
+    fn malformed() {
+        let mut deserialize = Deserialize::<(u32, Box<[u8]>)>::default();
+        let buf = bincode::options()
+            .serialize(&(42u32, Box::<[u8]>::from(&b"hello"[..])))
+            .unwrap();
+        let Some(NodeEvent::Handle((42, message))) =
+            deserialize.update(RxEvent::Receive(Cow::Borrowed(&buf)))
+        else {
+            unreachable!()
+        };
+        assert_eq!(&*message, b"hello");
+
+        // truncated
+        assert!(deserialize
+            .update(RxEvent::Receive(Cow::Borrowed(&buf[..buf.len() - 1])))
+            .is_none());
+        // length prefix claims far more bytes than the datagram has
+        let forged = bincode::options().serialize(&(42u32, u64::MAX)).unwrap();
+        assert!(deserialize
+            .update(RxEvent::Receive(Cow::Borrowed(&forged)))
+            .is_none());
+        assert_eq!(deserialize.drop_count, 2);
+    }
+
+    #[test]
+    fn batched_receive() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        // the port is free after the socket is dropped, so sending to it is refused, and the
+        // refusal is reported to the connected socket by its next receiving
+        let sender_addr = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        socket.connect(sender_addr).unwrap();
+        socket.send(&[WHOLE]).unwrap();
+        sleep(Duration::from_millis(10));
+
+        let sender = UdpSocket::bind(sender_addr).unwrap();
+        init_socket(&socket);
+        let addr = socket.local_addr().unwrap();
+        // more than one batch, all queued before the first receiving
+        for i in 0..3 * BATCH_SIZE as u32 {
+            sender
+                .send_to(&[&[WHOLE], &i.to_le_bytes()[..]].concat(), addr)
+                .unwrap();
+        }
+        let (message_sender, receiver) = channel::unbounded();
+        let rx = spawn(move || {
+            capture_stop();
+            Rx(Arc::new(socket)).deploy(&mut |RxEvent::Receive(buf): RxEvent<'_>| {
+                message_sender.send(buf.into_owned()).unwrap()
+            })
+        });
+        for i in 0..3 * BATCH_SIZE as u32 {
+            assert_eq!(receiver.recv().unwrap(), i.to_le_bytes());
+        }
+        pthread_kill(rx.as_pthread_t(), Signal::SIGUSR1).unwrap();
+        rx.join().unwrap();
+    }
+
+    #[test]
+    fn transient_send_error() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        // the refusal is reported to the connected socket by its next sending, as in
+        // `batched_receive`
+        let refused_addr = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        socket.connect(refused_addr).unwrap();
+        socket.send(&[WHOLE]).unwrap();
+        sleep(Duration::from_millis(10));
+
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = receiver.local_addr().unwrap();
+        let mut tx = Tx::new(Arc::new(socket), Default::default());
+        tx.update(vec![
+            TxEvent::Send(addr, [0].into()),
+            TxEvent::Send(addr, [1].into()),
+        ]);
+        assert_eq!(tx.error_count, 1);
+        let mut buf = [0; 2];
+        assert_eq!(receiver.recv(&mut buf).unwrap(), 2);
+        assert_eq!(buf, [WHOLE, 1]);
+    }
+
+    #[test]
+    fn unhandled_timer() {
+        let mut serialize = Serialize::<u32>::default();
+        assert!(serialize
+            .update(NodeEffect::SetTimer(0, Duration::from_millis(10)))
+            .is_none());
+        assert!(serialize.update(NodeEffect::CancelTimer(0)).is_none());
+        assert!(matches!(
+            serialize.update(NodeEffect::Broadcast(42)),
+            Some(TxEvent::Broadcast(_))
+        ));
+    }
+
+    #[test]
+    fn fragmentation() {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut tx = Tx::new(socket, Default::default());
+        // 2 fragments, and not too many to overflow the receive buffer
+        let message = (0..100000).map(|i| i as u8).collect::<Vec<_>>();
+        tx.update(TxEvent::Send(
+            receiver.local_addr().unwrap(),
+            message.clone().into(),
+        ));
+
+        let mut datagrams = Vec::new();
+        let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+        for _ in 0..2 {
+            let len = receiver.recv(&mut buf).unwrap();
+            datagrams.push(buf[..len].to_vec());
+        }
+        let mut reassemble = Reassemble::default();
+        let mut messages = Vec::new();
+        fn receive(reassemble: &mut Reassemble, datagram: &[u8], messages: &mut Vec<Vec<u8>>) {
+            reassemble.receive(datagram, &mut |RxEvent::Receive(buf): RxEvent<'_>| {
+                messages.push(buf.into_owned())
+            })
+        }
+        // out of order and duplicated
+        receive(&mut reassemble, &datagrams[1], &mut messages);
+        receive(&mut reassemble, &datagrams[1], &mut messages);
+        receive(&mut reassemble, &datagrams[0], &mut messages);
+        // a late duplicate starts a new reassembly that never completes
+        receive(&mut reassemble, &datagrams[0], &mut messages);
+        assert_eq!(messages, [message]);
+        assert_eq!(reassemble.messages.len(), 1);
+
+        // otherwise completed with the pending fragment, if it had not expired
+        reassemble.timeout = Duration::ZERO;
+        receive(&mut reassemble, &datagrams[1], &mut messages);
+        assert_eq!(messages.len(), 1);
+    }
+
+    fn fragment(id: u64, index: u16, count: u16, payload: &[u8]) -> Vec<u8> {
+        [
+            &[FRAGMENT][..],
+            &id.to_le_bytes(),
+            &index.to_le_bytes(),
+            &count.to_le_bytes(),
+            payload,
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn reassembly_limits() {
+        let mut reassemble = Reassemble::default();
+        let receive = |reassemble: &mut Reassemble, datagram: &[u8]| {
+            reassemble.receive(datagram, &mut |_: RxEvent<'_>| unreachable!())
+        };
+        // empty payload
+        receive(&mut reassemble, &fragment(0, 0, 2, &[]));
+        assert!(reassemble.messages.is_empty());
+        // only the received fragment is stored regardless of the claimed count
+        receive(&mut reassemble, &fragment(0, 0, 1024, &[42]));
+        assert_eq!(reassemble.size, 1);
+
+        for id in 1..2 * MAX_REASSEMBLY_COUNT as u64 {
+            receive(&mut reassemble, &fragment(id, 0, 2, &[42]));
+        }
+        assert_eq!(reassemble.messages.len(), MAX_REASSEMBLY_COUNT);
+        assert_eq!(reassemble.size, MAX_REASSEMBLY_COUNT);
+
+        reassemble.max_size = MAX_REASSEMBLY_COUNT + 1;
+        receive(&mut reassemble, &fragment(0, 1, 1024, &[42, 42]));
+        assert_eq!(reassemble.size, MAX_REASSEMBLY_COUNT);
+        receive(&mut reassemble, &fragment(0, 1, 1024, &[42]));
+        assert_eq!(reassemble.size, MAX_REASSEMBLY_COUNT + 1);
+
+        // the memory is released by expiring, and only the new fragment is pending
+        reassemble.timeout = Duration::ZERO;
+        receive(&mut reassemble, &fragment(0, 0, 2, &[42]));
+        assert_eq!(reassemble.messages.len(), 1);
+        assert_eq!(reassemble.size, 1);
+    }
+
+    #[test]
+    fn nonblocking_fragments() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        init_socket(&socket);
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        init_socket(&receiver);
+        // capped by `net.core.rmem_max`
+        setsockopt(receiver.as_raw_fd(), RcvBuf, &(8 << 20)).unwrap();
+        // as many fragments as the receive buffer holds, so that they are not dropped even if
+        // they are all sent before receiving any
+        let num_fragment = (getsockopt(receiver.as_raw_fd(), RcvBuf).unwrap()
+            / (2 * MAX_DATAGRAM_SIZE))
+            .clamp(2, 32);
+        let receiver = Arc::new(receiver);
+        let addr = receiver.local_addr().unwrap();
+        let (sender, messages) = channel::unbounded();
+        let rx = spawn(move || {
+            capture_stop();
+            Rx(receiver).deploy(&mut |RxEvent::Receive(buf): RxEvent<'_>| {
+                sender.send(buf.into_owned()).unwrap()
+            })
+        });
+
+        let message = (0..num_fragment * (MAX_DATAGRAM_SIZE - FRAGMENT_HEADER_SIZE))
+            .map(|i| i as u8)
+            .collect::<Box<[u8]>>();
+        let mut tx = Tx::new(Arc::new(socket), Default::default());
+        tx.update(TxEvent::Send(addr, message.clone()));
+        assert_eq!(
+            *messages.recv_timeout(Duration::from_secs(1)).unwrap(),
+            *message
+        );
+        pthread_kill(rx.as_pthread_t(), Signal::SIGUSR1).unwrap();
+        rx.join().unwrap();
     }
 }
@@ -0,0 +1,237 @@
+use std::{
+    borrow::Cow,
+    fs::remove_file,
+    io::ErrorKind,
+    marker::PhantomData,
+    os::{fd::AsRawFd, unix::net::UnixDatagram},
+    panic::panic_any,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use bincode::Options;
+use nix::{
+    errno::Errno,
+    poll::{ppoll, PollFd, PollFlags},
+    sys::{
+        signal::SigSet,
+        socket::{recv, MsgFlags},
+    },
+};
+
+use crate::{node::UnhandledTimer, protocol::Generate, NodeAddr, NodeEffect, Protocol};
+
+// only the addressing differs from `udp`, so the receiving side is shared
+pub use crate::udp::{Deserialize, RxEvent, RxEventOwned};
+
+// the socket of `NodeAddr::Unix(id)`, a stale socket file from previous run is removed
+// every node of a cluster should use the same `dir`, and different clusters can be isolated by
+// using different ones
+pub fn bind(dir: &Path, id: u32) -> UnixDatagram {
+    let path = dir.join(id.to_string());
+    match remove_file(&path) {
+        Err(err) if err.kind() != ErrorKind::NotFound => panic_any(err),
+        _ => {}
+    }
+    let socket = UnixDatagram::bind(path).unwrap();
+    socket.set_nonblocking(true).unwrap();
+    socket
+}
+
+pub struct Rx(pub Arc<UnixDatagram>);
+
+// larger datagrams are truncated by the kernel, and are dropped instead of being delivered
+const BUFFER_SIZE: usize = 65536;
+
+impl Generate for Rx {
+    type Event<'a> = RxEvent<'a>;
+
+    fn deploy<P>(&mut self, protocol: &mut P)
+    where
+        P: for<'a> Protocol<Self::Event<'a>>,
+    {
+        let mut buf = vec![0; BUFFER_SIZE];
+        let mut drop_count = 0u64;
+        loop {
+            match ppoll(
+                &mut [PollFd::new(self.0.as_raw_fd(), PollFlags::POLLIN)],
+                None,
+                Some(SigSet::empty()),
+            ) {
+                Err(Errno::EINTR) => break,
+                Err(err) => panic_any(err),
+                Ok(_) => {
+                    // the full length of the datagram is returned with `MSG_TRUNC`
+                    while let Ok(len) = recv(self.0.as_raw_fd(), &mut buf, MsgFlags::MSG_TRUNC) {
+                        if len > buf.len() {
+                            drop_count += 1;
+                            // log less as more are dropped, in case of flooding
+                            if drop_count.is_power_of_two() {
+                                eprintln!(
+                                    "dropped {drop_count} truncated datagram(s) of {len} bytes"
+                                );
+                            }
+                            continue;
+                        }
+                        protocol.update(RxEvent::Receive(Cow::Borrowed(&buf[..len])));
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub enum TxEvent {
+    Send(u32, Box<[u8]>),
+    Broadcast(Box<[u8]>),
+}
+
+pub struct Tx {
+    socket: Arc<UnixDatagram>,
+    dir: PathBuf,
+    broadcast: Box<[u32]>,
+}
+
+impl Tx {
+    // the ids are resolved in the directory that `socket` is bound in with `bind`, so the node is
+    // always addressed in the same directory that it is reachable in
+    pub fn new(socket: Arc<UnixDatagram>, broadcast: Box<[u32]>) -> Self {
+        let dir = socket
+            .local_addr()
+            .unwrap()
+            .as_pathname()
+            .and_then(Path::parent)
+            .expect("socket bound to a path")
+            .to_owned();
+        Self {
+            socket,
+            dir,
+            broadcast,
+        }
+    }
+
+    fn send(&self, id: u32, buf: &[u8]) {
+        // the destination may not be bound yet or have exited, or its queue is full, in all cases
+        // the datagram is lost as if over UDP
+        let _ = self.socket.send_to(buf, self.dir.join(id.to_string()));
+    }
+}
+
+impl Protocol<TxEvent> for Tx {
+    type Effect = ();
+
+    fn update(&mut self, event: TxEvent) -> Self::Effect {
+        match event {
+            TxEvent::Send(id, buf) => self.send(id, &buf),
+            TxEvent::Broadcast(buf) => {
+                for &id in &*self.broadcast {
+                    self.send(id, &buf)
+                }
+            }
+        }
+    }
+}
+
+pub struct Serialize<M>(PhantomData<M>);
+
+impl<M> Default for Serialize<M> {
+    fn default() -> Self {
+        Self(Default::default())
+    }
+}
+
+impl<M> Protocol<NodeEffect<M>> for Serialize<M>
+where
+    M: serde::Serialize,
+{
+    type Effect = Option<TxEvent>;
+
+    fn update(&mut self, event: NodeEffect<M>) -> Self::Effect {
+        Some(match event {
+            NodeEffect::Send(NodeAddr::Unix(id), message) => {
+                let buf = bincode::options().serialize(&message).unwrap().into();
+                TxEvent::Send(id, buf)
+            }
+            NodeEffect::Send(..) => panic!(),
+            NodeEffect::Broadcast(message) => {
+                let buf = bincode::options().serialize(&message).unwrap().into();
+                TxEvent::Broadcast(buf)
+            }
+            // should be intercepted by `node::Timer`
+            NodeEffect::SetTimer(id, _) | NodeEffect::CancelTimer(id) => {
+                UnhandledTimer(id).report();
+                return None;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env::temp_dir,
+        fs::{create_dir_all, remove_dir_all},
+        os::unix::{net::UnixDatagram, thread::JoinHandleExt},
+        process,
+        sync::Arc,
+        thread::spawn,
+    };
+
+    use crossbeam::channel;
+    use nix::sys::{pthread::pthread_kill, signal::Signal};
+
+    use crate::{capture_stop, protocol::Generate, NodeAddr, NodeEffect, NodeEvent, Protocol};
+
+    use super::{bind, Deserialize, Rx, RxEvent, Serialize, Tx, BUFFER_SIZE};
+
+    #[test]
+    fn send_and_broadcast() {
+        let dir = temp_dir().join(format!("dsys-uds-{}", process::id()));
+        create_dir_all(&dir).unwrap();
+        let sockets = (0..3).map(|id| bind(&dir, id)).collect::<Vec<_>>();
+        let mut tx =
+            Serialize::default().each_then(Tx::new(Arc::new(bind(&dir, 100)), [1, 2].into()));
+        tx.update(NodeEffect::Send(NodeAddr::Unix(0), 42u32));
+        tx.update(NodeEffect::Broadcast(43u32));
+        // lost silently
+        tx.update(NodeEffect::Send(NodeAddr::Unix(3), 44u32));
+
+        let mut buf = [0; 16];
+        for (socket, expected) in sockets.iter().zip([42, 43, 43]) {
+            let len = socket.recv(&mut buf).unwrap();
+            let Some(NodeEvent::Handle(message)) =
+                Deserialize::<u32>::default().update(RxEvent::Receive(buf[..len].into()))
+            else {
+                unreachable!()
+            };
+            assert_eq!(message, expected);
+        }
+        remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn receive() {
+        let dir = temp_dir().join(format!("dsys-uds-receive-{}", process::id()));
+        create_dir_all(&dir).unwrap();
+        let socket = Arc::new(bind(&dir, 0));
+        let (sender, receiver) = channel::unbounded();
+        let rx = spawn(move || {
+            capture_stop();
+            Rx(socket).deploy(&mut |RxEvent::Receive(buf): RxEvent<'_>| {
+                sender.send(buf.into_owned()).unwrap()
+            })
+        });
+
+        let client = UnixDatagram::unbound().unwrap();
+        let path = dir.join("0");
+        client.send_to(&[42], &path).unwrap();
+        // truncated and dropped
+        client.send_to(&vec![0; BUFFER_SIZE + 1], &path).unwrap();
+        client.send_to(&[43], &path).unwrap();
+        assert_eq!(receiver.recv().unwrap(), [42]);
+        assert_eq!(receiver.recv().unwrap(), [43]);
+        pthread_kill(rx.as_pthread_t(), Signal::SIGUSR1).unwrap();
+        rx.join().unwrap();
+        remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,10 +1,11 @@
-use std::{collections::HashMap, net::Ipv4Addr};
+use std::{collections::HashMap, net::Ipv4Addr, time::Duration};
 
 use serde::{Deserialize, Serialize};
 
 use crate::{
     app::App,
-    node::{ClientEffect, ClientEvent},
+    node::{ClientEffect, ClientEvent, UnexpectedMessage},
+    udp::BorrowMessage,
     NodeAddr, NodeEffect, NodeEvent, Protocol,
 };
 
@@ -16,6 +17,15 @@ pub struct Request {
     op: Box<[u8]>,
 }
 
+// the same encoding as `Request`, with `op` borrowed from the receive buffer
+#[derive(Debug, Deserialize)]
+pub struct RequestRef<'a> {
+    client_id: u32,
+    client_addr: NodeAddr,
+    seq: u32,
+    op: &'a [u8],
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Reply {
     seq: u32,
@@ -28,16 +38,30 @@ pub enum Message {
     Reply(Reply),
 }
 
+#[derive(Debug, Deserialize)]
+pub enum MessageRef<'a> {
+    #[serde(borrow)]
+    Request(RequestRef<'a>),
+    Reply(Reply),
+}
+
+impl BorrowMessage for MessageRef<'static> {
+    type Message<'a> = MessageRef<'a>;
+}
+
 pub struct Client {
     id: u32,
     addr: NodeAddr,
     replica_addr: NodeAddr,
     seq: u32,
     op: Option<Box<[u8]>>,
-    ticked: u32,
+    resend_count: u32,
     pub resend_stats: HashMap<u32, u32>,
 }
 
+// the timer id is the request's seq
+const RESEND_INTERVAL: Duration = Duration::from_millis(10);
+
 impl Client {
     pub fn new(id: u32, addr: NodeAddr, replica_addr: NodeAddr) -> Self {
         assert!(!matches!(addr, NodeAddr::Socket(addr) if addr.ip() == Ipv4Addr::UNSPECIFIED));
@@ -47,69 +71,69 @@ impl Client {
             replica_addr,
             seq: 0,
             op: None,
-            ticked: 0,
+            resend_count: 0,
             resend_stats: Default::default(),
         }
     }
+
+    fn send_request(&self) -> Vec<ClientEffect<Message>> {
+        let request = Request {
+            client_id: self.id,
+            client_addr: self.addr,
+            seq: self.seq,
+            op: self.op.clone().unwrap(),
+        };
+        vec![
+            ClientEffect::Node(NodeEffect::Send(
+                self.replica_addr,
+                Message::Request(request),
+            )),
+            ClientEffect::Node(NodeEffect::SetTimer(self.seq, RESEND_INTERVAL)),
+        ]
+    }
 }
 
 impl Protocol<ClientEvent<Message>> for Client {
-    type Effect = Option<ClientEffect<Message>>;
+    type Effect = Vec<ClientEffect<Message>>;
 
     fn update(&mut self, event: ClientEvent<Message>) -> Self::Effect {
         match event {
             ClientEvent::Op(op) => {
                 assert!(self.op.is_none());
-                self.op = Some(op.clone());
+                self.op = Some(op);
                 self.seq += 1;
-                self.ticked = 0;
-                let request = Request {
-                    client_id: self.id,
-                    client_addr: self.addr,
-                    seq: self.seq,
-                    op,
-                };
-                Some(ClientEffect::Node(NodeEffect::Send(
-                    self.replica_addr,
-                    Message::Request(request),
-                )))
+                self.resend_count = 0;
+                self.send_request()
             }
-            ClientEvent::Node(NodeEvent::Init) => None,
-            ClientEvent::Node(NodeEvent::Tick) => {
-                let Some(op) = &self.op else {
-                    return None;
-                };
-
-                self.ticked += 1;
-                assert_ne!(self.ticked, 10);
-                if self.ticked == 1 {
-                    return None;
+            ClientEvent::Node(NodeEvent::Init | NodeEvent::Tick) => Vec::new(),
+            ClientEvent::Node(NodeEvent::Timeout(seq)) => {
+                // the timer of a completed op, which timed out before it was canceled
+                if self.op.is_none() || seq != self.seq {
+                    return Vec::new();
                 }
 
-                if self.ticked == 2 {
+                // keep resending as long as the replica is unreachable
+                self.resend_count += 1;
+                if self.resend_count == 1 {
                     eprintln!("resend");
                 }
                 *self.resend_stats.entry(self.seq).or_default() += 1;
-
-                let request = Request {
-                    client_id: self.id,
-                    client_addr: self.addr,
-                    seq: self.seq,
-                    op: op.clone(),
-                };
-                Some(ClientEffect::Node(NodeEffect::Send(
-                    self.replica_addr,
-                    Message::Request(request),
-                )))
+                self.send_request()
             }
             ClientEvent::Node(NodeEvent::Handle(Message::Reply(reply))) => {
                 if self.op.is_none() || reply.seq != self.seq {
-                    return None;
+                    return Vec::new();
                 }
                 self.op = None;
-                Some(ClientEffect::Result(reply.result))
+                vec![
+                    ClientEffect::Node(NodeEffect::CancelTimer(self.seq)),
+                    ClientEffect::Result(reply.result),
+                ]
+            }
+            ClientEvent::Node(NodeEvent::Handle(Message::Request(_))) => {
+                UnexpectedMessage("request").report();
+                Vec::new()
             }
-            _ => unreachable!(),
         }
     }
 }
@@ -128,17 +152,8 @@ impl Replica {
             replies: Default::default(),
         }
     }
-}
-
-impl Protocol<NodeEvent<Message>> for Replica {
-    type Effect = Option<NodeEffect<Message>>;
 
-    fn update(&mut self, event: NodeEvent<Message>) -> Self::Effect {
-        let request = match event {
-            NodeEvent::Handle(Message::Request(request)) => request,
-            NodeEvent::Init | NodeEvent::Tick => return None,
-            _ => unreachable!(),
-        };
+    fn handle_request(&mut self, request: RequestRef) -> Option<NodeEffect<Message>> {
         match self.replies.get(&request.client_id) {
             Some(reply) if reply.seq > request.seq => return None,
             Some(reply) if reply.seq == request.seq => {
@@ -150,7 +165,7 @@ impl Protocol<NodeEvent<Message>> for Replica {
             _ => {}
         }
         self.op_num += 1;
-        let result = self.app.execute(&request.op);
+        let result = self.app.execute(request.op);
         let reply = Reply {
             seq: request.seq,
             result,
@@ -160,18 +175,72 @@ impl Protocol<NodeEvent<Message>> for Replica {
     }
 }
 
+impl Protocol<NodeEvent<Message>> for Replica {
+    type Effect = Option<NodeEffect<Message>>;
+
+    fn update(&mut self, event: NodeEvent<Message>) -> Self::Effect {
+        let request = match event {
+            NodeEvent::Handle(Message::Request(request)) => request,
+            NodeEvent::Handle(Message::Reply(_)) => {
+                UnexpectedMessage("reply").report();
+                return None;
+            }
+            NodeEvent::Init | NodeEvent::Tick | NodeEvent::Timeout(_) => return None,
+        };
+        self.handle_request(RequestRef {
+            client_id: request.client_id,
+            client_addr: request.client_addr,
+            seq: request.seq,
+            op: &request.op,
+        })
+    }
+}
+
+// the zero-copy path, which handles the messages decoded by `udp::DeserializeBorrowed` in the
+// `udp::Rx` thread
+// a separate type because another `Protocol` impl on `Replica` breaks the inference of `then`
+pub struct ZeroCopyReplica(pub Replica);
+
+impl Protocol<NodeEvent<MessageRef<'_>>> for ZeroCopyReplica {
+    type Effect = Option<NodeEffect<Message>>;
+
+    fn update(&mut self, event: NodeEvent<MessageRef<'_>>) -> Self::Effect {
+        match event {
+            NodeEvent::Handle(MessageRef::Request(request)) => self.0.handle_request(request),
+            NodeEvent::Handle(MessageRef::Reply(_)) => {
+                UnexpectedMessage("reply").report();
+                None
+            }
+            NodeEvent::Init | NodeEvent::Tick | NodeEvent::Timeout(_) => None,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::{
+        borrow::Cow,
+        sync::{
+            atomic::{AtomicU8, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use bincode::Options;
+
     use crate::{
         app,
-        node::Workload,
+        node::{ClientEffect, ClientEvent, Workload, WorkloadMode},
         protocol::OneOf,
+        simulate::Fault,
+        udp::{DeserializeBorrowed, RxEvent},
         App,
         NodeAddr::{TestClient, TestReplica},
-        Protocol, Simulate,
+        NodeEffect, NodeEvent, Protocol, Simulate,
     };
 
-    use super::{Client, Message, Replica};
+    use super::{Client, Message, MessageRef, Replica, Reply, Request, ZeroCopyReplica};
 
     #[test]
     fn single_op() {
@@ -198,4 +267,189 @@ mod tests {
         assert_eq!(workload.results.len(), 1);
         assert_eq!(&*workload.results[0], &b"hello"[..]);
     }
+
+    #[test]
+    fn faulty_network() {
+        let mut simulate = Simulate::<_, Message>::default();
+        simulate.nodes.insert(
+            TestClient(0),
+            OneOf::A(Workload::new_test(
+                Client::new(0, TestClient(0), TestReplica(0)),
+                (0..100).map(|i| format!("op {i}").into_bytes()),
+            )),
+        );
+        simulate.nodes.insert(
+            TestReplica(0),
+            OneOf::B(
+                Replica::new(App::Echo(app::Echo))
+                    .then(|effect: Option<_>| effect.into_iter().collect()),
+            ),
+        );
+        simulate.set_fault(Fault {
+            drop: 0.2,
+            duplicate: 0.2,
+            delay: 0.2,
+        });
+        simulate.init();
+        assert!(simulate.run_until_predicate(
+            |simulate| {
+                let OneOf::A(workload) = &simulate.nodes[&TestClient(0)] else {
+                    unreachable!()
+                };
+                workload.results.len() == 100
+            },
+            Duration::from_secs(10),
+        ));
+        let OneOf::A(workload) = &simulate.nodes[&TestClient(0)] else {
+            unreachable!()
+        };
+        for (i, result) in workload.results.iter().enumerate() {
+            assert_eq!(&**result, format!("op {i}").as_bytes());
+        }
+        assert!(!workload.node.resend_stats.is_empty());
+    }
+
+    #[test]
+    fn resend_until_reply() {
+        let mut client = Client::new(0, TestClient(0), TestReplica(0));
+        client.update(ClientEvent::Op(b"hello".to_vec().into()));
+        // the replica is unreachable for long
+        for _ in 0..20 {
+            let effects = client.update(ClientEvent::Node(NodeEvent::Timeout(1)));
+            assert!(matches!(
+                &effects[..],
+                [
+                    ClientEffect::Node(NodeEffect::Send(TestReplica(0), Message::Request(_))),
+                    ClientEffect::Node(NodeEffect::SetTimer(1, _))
+                ]
+            ));
+        }
+        let reply = Reply {
+            seq: 1,
+            result: b"hello".to_vec().into(),
+        };
+        client.update(ClientEvent::Node(NodeEvent::Handle(Message::Reply(reply))));
+        // timed out before canceled
+        assert!(client
+            .update(ClientEvent::Node(NodeEvent::Timeout(1)))
+            .is_empty());
+        assert_eq!(client.resend_stats[&1], 20);
+    }
+
+    #[test]
+    fn switch_mode_with_outstanding_op() {
+        let mut simulate = Simulate::<_, Message>::default();
+        let mode = Arc::new(AtomicU8::new(WorkloadMode::Discard as _));
+        simulate.nodes.insert(
+            TestClient(0),
+            OneOf::A(Workload::new_benchmark(
+                Client::new(0, TestClient(0), TestReplica(0)),
+                (0..2).map(|i| format!("op {i}").into_bytes()),
+                mode.clone(),
+            )),
+        );
+        simulate.nodes.insert(
+            TestReplica(0),
+            OneOf::B(
+                Replica::new(App::Echo(app::Echo))
+                    .then(|effect: Option<_>| effect.into_iter().collect()),
+            ),
+        );
+        // the first op is invoked in discard mode and completes in test mode
+        simulate.init();
+        mode.store(WorkloadMode::Test as _, Ordering::SeqCst);
+        while simulate.progress() {}
+        let OneOf::A(workload) = &simulate.nodes[&TestClient(0)] else {
+            unreachable!()
+        };
+        assert_eq!(workload.results, [b"op 0"[..].into(), b"op 1"[..].into()]);
+        // only test workloads record history
+        assert!(workload.history.is_empty());
+    }
+
+    #[test]
+    fn zero_copy() {
+        let request = Message::Request(Request {
+            client_id: 0,
+            client_addr: TestClient(0),
+            seq: 1,
+            op: b"hello".to_vec().into(),
+        });
+        let buf = bincode::options().serialize(&request).unwrap();
+
+        let Some(NodeEvent::Handle(MessageRef::Request(request))) =
+            DeserializeBorrowed::<MessageRef<'static>>::default()
+                .update(RxEvent::Receive(Cow::Borrowed(&buf)))
+        else {
+            unreachable!()
+        };
+        assert_eq!(request.op, b"hello");
+        assert!(buf.as_ptr_range().contains(&request.op.as_ptr()));
+
+        let mut effects = Vec::new();
+        DeserializeBorrowed::<MessageRef<'static>>::default()
+            .each_then(
+                ZeroCopyReplica(Replica::new(App::Echo(app::Echo)))
+                    .each_then(|effect| effects.push(effect)),
+            )
+            .update(RxEvent::Receive(Cow::Borrowed(&buf)));
+        let [NodeEffect::Send(TestClient(0), Message::Reply(reply))] = &effects[..] else {
+            unreachable!()
+        };
+        assert_eq!(reply.seq, 1);
+        assert_eq!(&*reply.result, b"hello");
+    }
+
+    #[test]
+    fn ignore_reply() {
+        let mut replica = Replica::new(App::Echo(app::Echo));
+        let reply = Reply {
+            seq: 1,
+            result: b"hello".to_vec().into(),
+        };
+        assert!(replica
+            .update(NodeEvent::Handle(Message::Reply(reply.clone())))
+            .is_none());
+        assert!(ZeroCopyReplica(replica)
+            .update(NodeEvent::Handle(MessageRef::Reply(reply)))
+            .is_none());
+    }
+
+    // `MessageRef` mirrors `Message` by hand, so every variant should decode from the encoding of
+    // its `Message` counterpart
+    #[test]
+    fn borrowed_encoding() {
+        let request = Request {
+            client_id: 1,
+            client_addr: TestClient(1),
+            seq: 2,
+            op: b"hello".to_vec().into(),
+        };
+        let reply = Reply {
+            seq: 3,
+            result: b"world".to_vec().into(),
+        };
+        for message in [Message::Request(request), Message::Reply(reply)] {
+            let buf = bincode::options().serialize(&message).unwrap();
+            let Some(NodeEvent::Handle(message_ref)) =
+                DeserializeBorrowed::<MessageRef<'static>>::default()
+                    .update(RxEvent::Receive(Cow::Borrowed(&buf)))
+            else {
+                unreachable!()
+            };
+            match (message, message_ref) {
+                (Message::Request(request), MessageRef::Request(request_ref)) => {
+                    assert_eq!(request_ref.client_id, request.client_id);
+                    assert_eq!(request_ref.client_addr, request.client_addr);
+                    assert_eq!(request_ref.seq, request.seq);
+                    assert_eq!(request_ref.op, &*request.op);
+                }
+                (Message::Reply(reply), MessageRef::Reply(reply_ref)) => {
+                    assert_eq!(reply_ref.seq, reply.seq);
+                    assert_eq!(reply_ref.result, reply.result);
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
 }
@@ -0,0 +1,189 @@
+use std::{
+    net::UdpSocket,
+    os::fd::AsRawFd,
+    panic::panic_any,
+    sync::{
+        atomic::{AtomicU16, Ordering},
+        Arc,
+    },
+};
+
+use io_uring::{
+    cqueue, opcode,
+    types::{self, BufRingEntry},
+    IoUring,
+};
+use nix::{
+    errno::Errno,
+    poll::{ppoll, PollFd, PollFlags},
+    sys::signal::SigSet,
+};
+
+use crate::{
+    protocol::Generate,
+    udp::{is_transient, Reassemble, RxEvent},
+    Protocol,
+};
+
+// a drop-in replacement of `udp::Rx`, which receives with a single multishot recv request
+// datagrams are received into the buffers of a ring registered to the kernel in advance, and every
+// buffer is put back to the ring after the datagram in it is delivered, without a submission
+pub struct Rx(pub Arc<UdpSocket>);
+
+// must be a power of two for the buffer ring
+const NUM_BUFFER: u16 = 256;
+const BUFFER_SIZE: usize = 65536;
+const BUFFER_GROUP: u16 = 0;
+
+// the ring of buffers that the kernel picks from, shared with the kernel the same way as the
+// submission queue, and must be page aligned
+#[repr(C, align(4096))]
+struct BufRingEntries([BufRingEntry; NUM_BUFFER as usize]);
+
+struct BufRing {
+    entries: Box<BufRingEntries>,
+    tail: u16,
+}
+
+impl BufRing {
+    fn new() -> Self {
+        Self {
+            // every entry is plain integers
+            entries: unsafe { Box::new_zeroed().assume_init() },
+            tail: 0,
+        }
+    }
+
+    fn push(&mut self, buf: &mut [u8], id: u16) {
+        let entry = &mut self.entries.0[(self.tail & (NUM_BUFFER - 1)) as usize];
+        entry.set_addr(buf.as_mut_ptr() as _);
+        entry.set_len(buf.len() as _);
+        entry.set_bid(id);
+        self.tail = self.tail.wrapping_add(1);
+    }
+
+    // make the pushed buffers available to the kernel
+    fn publish(&mut self) {
+        let tail = unsafe { BufRingEntry::tail(self.entries.0.as_ptr()) };
+        unsafe { AtomicU16::from_ptr(tail as *mut u16) }.store(self.tail, Ordering::Release)
+    }
+}
+
+const RECV: u64 = 0;
+
+impl Generate for Rx {
+    type Event<'a> = RxEvent<'a>;
+
+    fn deploy<P>(&mut self, protocol: &mut P)
+    where
+        P: for<'a> Protocol<Self::Event<'a>>,
+    {
+        // declared before the ring so they are dropped after the ring, i.e. after the kernel is
+        // done with them
+        let mut bufs = vec![0u8; NUM_BUFFER as usize * BUFFER_SIZE];
+        let mut buf_ring = BufRing::new();
+        let mut ring = IoUring::new(NUM_BUFFER as u32).unwrap();
+        let mut reassemble = Reassemble::default();
+        let mut error_count = 0u64;
+        for (id, buf) in bufs.chunks_mut(BUFFER_SIZE).enumerate() {
+            buf_ring.push(buf, id as _)
+        }
+        buf_ring.publish();
+        unsafe {
+            ring.submitter().register_buf_ring_with_flags(
+                buf_ring.entries.0.as_ptr() as _,
+                NUM_BUFFER,
+                BUFFER_GROUP,
+                0,
+            )
+        }
+        .unwrap();
+        let recv = opcode::RecvMulti::new(types::Fd(self.0.as_raw_fd()), BUFFER_GROUP)
+            .build()
+            .user_data(RECV);
+        unsafe { ring.submission().push(&recv) }.unwrap();
+
+        loop {
+            ring.submit().unwrap();
+            // wait on the ring fd instead of `submit_and_wait`, the sigmask of which has mismatched
+            // size with the kernel's, and is rejected
+            if ring.completion().is_empty() {
+                match ppoll(
+                    &mut [PollFd::new(ring.as_raw_fd(), PollFlags::POLLIN)],
+                    None,
+                    Some(SigSet::empty()),
+                ) {
+                    Err(Errno::EINTR) => break,
+                    Err(err) => panic_any(err),
+                    Ok(_) => {}
+                }
+            }
+            let completions = ring
+                .completion()
+                .map(|entry| (entry.user_data(), entry.result(), entry.flags()))
+                .collect::<Vec<_>>();
+            for (user_data, result, flags) in completions {
+                assert_eq!(user_data, RECV);
+                // run out of buffers, which are put back below, so only need to rearm
+                if result == -(Errno::ENOBUFS as i32) {
+                } else if result < 0 {
+                    let err = Errno::from_i32(-result);
+                    if !is_transient(err) {
+                        panic_any(err)
+                    }
+                    error_count += 1;
+                    // log less as more occur, in case of flooding
+                    if error_count.is_power_of_two() {
+                        eprintln!("{error_count} receive error(s): {err}");
+                    }
+                } else {
+                    let id = cqueue::buffer_select(flags).unwrap();
+                    let buf = &mut bufs[id as usize * BUFFER_SIZE..][..BUFFER_SIZE];
+                    reassemble.receive(&buf[..result as usize], protocol);
+                    buf_ring.push(buf, id);
+                }
+                if !cqueue::more(flags) {
+                    unsafe { ring.submission().push(&recv) }.unwrap();
+                }
+            }
+            buf_ring.publish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::UdpSocket, os::unix::thread::JoinHandleExt, sync::Arc, thread::spawn};
+
+    use crossbeam::channel;
+    use nix::sys::{pthread::pthread_kill, signal::Signal};
+
+    use crate::{capture_stop, protocol::Generate, udp::RxEvent};
+
+    use super::Rx;
+
+    #[test]
+    fn receive() {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+        let addr = socket.local_addr().unwrap();
+        let (sender, receiver) = channel::unbounded();
+        let rx = spawn(move || {
+            capture_stop();
+            Rx(socket).deploy(&mut |RxEvent::Receive(buf): RxEvent<'_>| {
+                sender.send(buf.into_owned()).unwrap()
+            })
+        });
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        // more than the buffers in the ring, so they must be recycled
+        for i in 0..1000u32 {
+            // with the tag of a whole message
+            client
+                .send_to(&[&[0], &i.to_le_bytes()[..]].concat(), addr)
+                .unwrap();
+            assert_eq!(receiver.recv().unwrap(), i.to_le_bytes());
+        }
+        pthread_kill(rx.as_pthread_t(), Signal::SIGUSR1).unwrap();
+        rx.join().unwrap();
+    }
+}
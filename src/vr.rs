@@ -0,0 +1,993 @@
+use std::collections::{HashMap, HashSet};
+
+use rand::random;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app::App,
+    node::{ClientEffect, ClientEvent, ClientState, Request, UnexpectedMessage},
+    NodeAddr, NodeEffect, NodeEvent, Protocol,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reply {
+    view: u32,
+    seq: u32,
+    result: Box<[u8]>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Prepare {
+    view: u32,
+    op_num: u32,
+    commit_num: u32,
+    request: Request,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrepareOk {
+    view: u32,
+    op_num: u32,
+    replica_id: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Commit {
+    view: u32,
+    commit_num: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StartViewChange {
+    view: u32,
+    replica_id: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DoViewChange {
+    view: u32,
+    log: Vec<Request>,
+    last_normal_view: u32,
+    commit_num: u32,
+    replica_id: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StartView {
+    view: u32,
+    log: Vec<Request>,
+    commit_num: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recovery {
+    replica_id: u32,
+    nonce: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecoveryResponse {
+    view: u32,
+    nonce: u64,
+    // only the primary of `view` responds with its log
+    log: Option<Vec<Request>>,
+    commit_num: u32,
+    replica_id: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GetState {
+    view: u32,
+    op_num: u32,
+    replica_id: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NewState {
+    view: u32,
+    // the log entries after `op_num`
+    op_num: u32,
+    log: Vec<Request>,
+    commit_num: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Message {
+    Request(Request),
+    Reply(Reply),
+    Prepare(Prepare),
+    PrepareOk(PrepareOk),
+    Commit(Commit),
+    StartViewChange(StartViewChange),
+    DoViewChange(DoViewChange),
+    StartView(StartView),
+    Recovery(Recovery),
+    RecoveryResponse(RecoveryResponse),
+    GetState(GetState),
+    NewState(NewState),
+}
+
+pub struct Client {
+    pub state: ClientState,
+    replicas: Box<[NodeAddr]>,
+    view: u32,
+}
+
+impl Client {
+    pub fn new(id: u32, addr: NodeAddr, replicas: Box<[NodeAddr]>) -> Self {
+        Self {
+            state: ClientState::new(id, addr),
+            replicas,
+            view: 0,
+        }
+    }
+}
+
+impl Protocol<ClientEvent<Message>> for Client {
+    type Effect = Option<ClientEffect<Message>>;
+
+    fn update(&mut self, event: ClientEvent<Message>) -> Self::Effect {
+        match event {
+            ClientEvent::Op(op) => {
+                let request = self.state.invoke(op);
+                let primary = self.replicas[self.view as usize % self.replicas.len()];
+                Some(ClientEffect::Node(NodeEffect::Send(
+                    primary,
+                    Message::Request(request),
+                )))
+            }
+            ClientEvent::Node(NodeEvent::Init) => None,
+            ClientEvent::Node(NodeEvent::Tick) => {
+                let request = self.state.tick()?;
+                // the primary may have changed, so let every replica know about the request
+                Some(ClientEffect::Node(NodeEffect::Broadcast(Message::Request(
+                    request,
+                ))))
+            }
+            ClientEvent::Node(NodeEvent::Handle(Message::Reply(reply))) => {
+                if !self.state.is_outstanding(reply.seq) {
+                    return None;
+                }
+                self.state.complete();
+                self.view = self.view.max(reply.view);
+                Some(ClientEffect::Result(reply.result))
+            }
+            ClientEvent::Node(NodeEvent::Handle(_)) => None,
+            ClientEvent::Node(NodeEvent::Timeout(_)) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Normal,
+    ViewChange,
+    Recovering,
+}
+
+struct ClientEntry {
+    seq: u32,
+    // `None` if the request is prepared but not yet committed
+    reply: Option<Reply>,
+}
+
+pub struct Replica {
+    id: u32,
+    replicas: Box<[NodeAddr]>,
+    app: App,
+    status: Status,
+    view: u32,
+    last_normal_view: u32,
+    // the request with op number `n` is `log[n - 1]`
+    log: Vec<Request>,
+    commit_num: u32,
+    client_table: HashMap<u32, ClientEntry>,
+    // on primary, the highest op number each backup has acknowledged in the current view
+    acked: HashMap<u32, u32>,
+    start_view_changes: HashSet<u32>,
+    do_view_changes: HashMap<u32, DoViewChange>,
+    nonce: u64,
+    recovery_responses: HashMap<u32, RecoveryResponse>,
+    // primary: ticks since the last message to backups
+    // otherwise: ticks since the last message from primary (or since the status changed)
+    ticked: u32,
+}
+
+const HEARTBEAT_TICKS: u32 = 2;
+const VIEW_CHANGE_TICKS: u32 = 10;
+
+impl Replica {
+    pub fn new(id: u32, replicas: Box<[NodeAddr]>, app: App) -> Self {
+        assert!((id as usize) < replicas.len());
+        Self {
+            id,
+            replicas,
+            app,
+            status: Status::Normal,
+            view: 0,
+            last_normal_view: 0,
+            log: Default::default(),
+            commit_num: 0,
+            client_table: Default::default(),
+            acked: Default::default(),
+            start_view_changes: Default::default(),
+            do_view_changes: Default::default(),
+            nonce: 0,
+            recovery_responses: Default::default(),
+            ticked: 0,
+        }
+    }
+
+    // a replica that lost its state, e.g. restarted after crashing, and has to run the recovery
+    // protocol before participating again
+    pub fn recover(id: u32, replicas: Box<[NodeAddr]>, app: App) -> Self {
+        Self {
+            status: Status::Recovering,
+            nonce: random(),
+            ..Self::new(id, replicas, app)
+        }
+    }
+
+    fn f(&self) -> usize {
+        (self.replicas.len() - 1) / 2
+    }
+
+    fn primary(&self, view: u32) -> u32 {
+        view % self.replicas.len() as u32
+    }
+
+    fn is_primary(&self) -> bool {
+        self.primary(self.view) == self.id
+    }
+
+    fn op_num(&self) -> u32 {
+        self.log.len() as u32
+    }
+
+    fn send_replica(&self, id: u32, message: Message) -> NodeEffect<Message> {
+        NodeEffect::Send(self.replicas[id as usize], message)
+    }
+
+    fn send_primary(&self, message: Message) -> NodeEffect<Message> {
+        self.send_replica(self.primary(self.view), message)
+    }
+
+    fn prepare_ok(&self) -> NodeEffect<Message> {
+        self.send_primary(Message::PrepareOk(PrepareOk {
+            view: self.view,
+            op_num: self.op_num(),
+            replica_id: self.id,
+        }))
+    }
+
+    fn execute_up_to(&mut self, commit_num: u32) -> Vec<NodeEffect<Message>> {
+        let mut effects = Vec::new();
+        let commit_num = commit_num.min(self.op_num());
+        while self.commit_num < commit_num {
+            self.commit_num += 1;
+            let request = &self.log[self.commit_num as usize - 1];
+            // the same request can be logged twice if the client resent it across a view change
+            if matches!(
+                self.client_table.get(&request.client_id),
+                Some(ClientEntry { seq, reply: Some(_) }) if *seq >= request.seq
+            ) {
+                continue;
+            }
+            let reply = Reply {
+                view: self.view,
+                seq: request.seq,
+                result: self.app.execute(&request.op),
+            };
+            if self.is_primary() {
+                effects.push(NodeEffect::Send(
+                    request.client_addr,
+                    Message::Reply(reply.clone()),
+                ));
+            }
+            self.client_table.insert(
+                request.client_id,
+                ClientEntry {
+                    seq: request.seq,
+                    reply: Some(reply),
+                },
+            );
+        }
+        effects
+    }
+
+    fn commit_quorum(&mut self) -> Vec<NodeEffect<Message>> {
+        let f = self.f();
+        let quorum_num = if f == 0 {
+            self.op_num()
+        } else {
+            let mut acked = self.acked.values().copied().collect::<Vec<_>>();
+            acked.sort_unstable_by(|a, b| b.cmp(a));
+            acked.get(f - 1).copied().unwrap_or_default()
+        };
+        self.execute_up_to(quorum_num)
+    }
+
+    fn enter_view(&mut self, view: u32, log: Vec<Request>) {
+        self.view = view;
+        self.last_normal_view = view;
+        self.status = Status::Normal;
+        self.log = log;
+        self.ticked = 0;
+        self.acked.clear();
+        // the uncommitted requests are the ones in the new log, and a request dropped by the view
+        // change must be admitted again when it is resent
+        self.client_table.retain(|_, entry| entry.reply.is_some());
+        for request in &self.log[self.commit_num.min(self.op_num()) as usize..] {
+            if !matches!(
+                self.client_table.get(&request.client_id),
+                Some(entry) if entry.seq >= request.seq
+            ) {
+                self.client_table.insert(
+                    request.client_id,
+                    ClientEntry {
+                        seq: request.seq,
+                        reply: None,
+                    },
+                );
+            }
+        }
+    }
+
+    // the replica missed a view change, and it only knows that the committed prefix of its log is
+    // still valid
+    fn state_transfer(&mut self, view: u32) -> Vec<NodeEffect<Message>> {
+        let mut log = std::mem::take(&mut self.log);
+        log.truncate(self.commit_num as usize);
+        self.enter_view(view, log);
+        vec![self.send_primary(Message::GetState(GetState {
+            view: self.view,
+            op_num: self.op_num(),
+            replica_id: self.id,
+        }))]
+    }
+
+    fn start_view_change(&mut self, view: u32) -> Vec<NodeEffect<Message>> {
+        self.view = view;
+        self.status = Status::ViewChange;
+        self.ticked = 0;
+        self.acked.clear();
+        self.start_view_changes.clear();
+        self.do_view_changes.clear();
+        vec![NodeEffect::Broadcast(Message::StartViewChange(
+            StartViewChange {
+                view,
+                replica_id: self.id,
+            },
+        ))]
+    }
+
+    fn handle_request(&mut self, request: Request) -> Vec<NodeEffect<Message>> {
+        if self.status != Status::Normal || !self.is_primary() {
+            return Vec::new();
+        }
+        match self.client_table.get(&request.client_id) {
+            Some(entry) if entry.seq > request.seq => return Vec::new(),
+            Some(entry) if entry.seq == request.seq => {
+                return entry
+                    .reply
+                    .iter()
+                    .map(|reply| {
+                        NodeEffect::Send(request.client_addr, Message::Reply(reply.clone()))
+                    })
+                    .collect()
+            }
+            _ => {}
+        }
+        self.client_table.insert(
+            request.client_id,
+            ClientEntry {
+                seq: request.seq,
+                reply: None,
+            },
+        );
+        self.log.push(request.clone());
+        self.ticked = 0;
+        let mut effects = vec![NodeEffect::Broadcast(Message::Prepare(Prepare {
+            view: self.view,
+            op_num: self.op_num(),
+            commit_num: self.commit_num,
+            request,
+        }))];
+        effects.extend(self.commit_quorum());
+        effects
+    }
+
+    fn handle_prepare(&mut self, prepare: Prepare) -> Vec<NodeEffect<Message>> {
+        if self.status != Status::Normal || prepare.view < self.view {
+            return Vec::new();
+        }
+        if prepare.view > self.view {
+            return self.state_transfer(prepare.view);
+        }
+        self.ticked = 0;
+        let mut effects = Vec::new();
+        if prepare.op_num == self.op_num() + 1 {
+            self.log.push(prepare.request);
+            effects.push(self.prepare_ok());
+        } else if prepare.op_num <= self.op_num() {
+            // the acknowledgement may be lost, or the primary is resending to make progress
+            effects.push(self.prepare_ok());
+        } else {
+            effects.push(self.send_primary(Message::GetState(GetState {
+                view: self.view,
+                op_num: self.op_num(),
+                replica_id: self.id,
+            })));
+        }
+        effects.extend(self.execute_up_to(prepare.commit_num));
+        effects
+    }
+
+    fn handle_prepare_ok(&mut self, prepare_ok: PrepareOk) -> Vec<NodeEffect<Message>> {
+        if self.status != Status::Normal || prepare_ok.view != self.view || !self.is_primary() {
+            return Vec::new();
+        }
+        let acked = self.acked.entry(prepare_ok.replica_id).or_default();
+        *acked = (*acked).max(prepare_ok.op_num);
+        self.commit_quorum()
+    }
+
+    fn handle_commit(&mut self, commit: Commit) -> Vec<NodeEffect<Message>> {
+        if self.status != Status::Normal || commit.view < self.view {
+            return Vec::new();
+        }
+        if commit.view > self.view {
+            return self.state_transfer(commit.view);
+        }
+        self.ticked = 0;
+        let mut effects = Vec::new();
+        if commit.commit_num > self.op_num() {
+            effects.push(self.send_primary(Message::GetState(GetState {
+                view: self.view,
+                op_num: self.op_num(),
+                replica_id: self.id,
+            })));
+        }
+        effects.extend(self.execute_up_to(commit.commit_num));
+        effects
+    }
+
+    fn handle_start_view_change(
+        &mut self,
+        start_view_change: StartViewChange,
+    ) -> Vec<NodeEffect<Message>> {
+        if self.status == Status::Recovering || start_view_change.view < self.view {
+            return Vec::new();
+        }
+        let mut effects = Vec::new();
+        if start_view_change.view > self.view {
+            effects.extend(self.start_view_change(start_view_change.view));
+        }
+        if self.status != Status::ViewChange {
+            return effects;
+        }
+        self.start_view_changes.insert(start_view_change.replica_id);
+        if self.start_view_changes.len() == self.f() {
+            let do_view_change = DoViewChange {
+                view: self.view,
+                log: self.log.clone(),
+                last_normal_view: self.last_normal_view,
+                commit_num: self.commit_num,
+                replica_id: self.id,
+            };
+            if self.primary(self.view) == self.id {
+                effects.extend(self.handle_do_view_change(do_view_change));
+            } else {
+                effects.push(self.send_primary(Message::DoViewChange(do_view_change)));
+            }
+        }
+        effects
+    }
+
+    fn handle_do_view_change(&mut self, do_view_change: DoViewChange) -> Vec<NodeEffect<Message>> {
+        if self.status == Status::Recovering || do_view_change.view < self.view {
+            return Vec::new();
+        }
+        let mut effects = Vec::new();
+        if do_view_change.view > self.view {
+            effects.extend(self.start_view_change(do_view_change.view));
+        }
+        if self.status != Status::ViewChange || !self.is_primary() {
+            return effects;
+        }
+        self.do_view_changes
+            .insert(do_view_change.replica_id, do_view_change);
+        if self.do_view_changes.len() != self.f() + 1 {
+            return effects;
+        }
+
+        let do_view_changes = std::mem::take(&mut self.do_view_changes);
+        let commit_num = do_view_changes
+            .values()
+            .map(|do_view_change| do_view_change.commit_num)
+            .max()
+            .unwrap()
+            .max(self.commit_num);
+        let log = do_view_changes
+            .into_values()
+            .max_by_key(|do_view_change| {
+                (
+                    do_view_change.last_normal_view,
+                    do_view_change.log.len(),
+                    do_view_change.replica_id,
+                )
+            })
+            .unwrap()
+            .log;
+        self.enter_view(self.view, log);
+        effects.push(NodeEffect::Broadcast(Message::StartView(StartView {
+            view: self.view,
+            log: self.log.clone(),
+            commit_num,
+        })));
+        effects.extend(self.execute_up_to(commit_num));
+        effects
+    }
+
+    fn handle_start_view(&mut self, start_view: StartView) -> Vec<NodeEffect<Message>> {
+        if self.status == Status::Recovering
+            || start_view.view < self.view
+            || (start_view.view == self.view && self.status == Status::Normal)
+        {
+            return Vec::new();
+        }
+        self.enter_view(start_view.view, start_view.log);
+        let mut effects = Vec::new();
+        if self.op_num() > start_view.commit_num {
+            effects.push(self.prepare_ok());
+        }
+        effects.extend(self.execute_up_to(start_view.commit_num));
+        effects
+    }
+
+    fn handle_recovery(&mut self, recovery: Recovery) -> Vec<NodeEffect<Message>> {
+        if self.status != Status::Normal {
+            return Vec::new();
+        }
+        let response = RecoveryResponse {
+            view: self.view,
+            nonce: recovery.nonce,
+            log: if self.is_primary() {
+                Some(self.log.clone())
+            } else {
+                None
+            },
+            commit_num: self.commit_num,
+            replica_id: self.id,
+        };
+        vec![self.send_replica(recovery.replica_id, Message::RecoveryResponse(response))]
+    }
+
+    fn handle_recovery_response(&mut self, response: RecoveryResponse) -> Vec<NodeEffect<Message>> {
+        if self.status != Status::Recovering || response.nonce != self.nonce {
+            return Vec::new();
+        }
+        self.recovery_responses
+            .insert(response.replica_id, response);
+        if self.recovery_responses.len() < self.f() + 1 {
+            return Vec::new();
+        }
+        let view = self
+            .recovery_responses
+            .values()
+            .map(|response| response.view)
+            .max()
+            .unwrap();
+        // keep every response until the primary of the latest view responds in that view
+        if !matches!(
+            self.recovery_responses.get(&self.primary(view)),
+            Some(response) if response.view == view
+        ) {
+            return Vec::new();
+        }
+        let primary_response = self.recovery_responses.remove(&self.primary(view)).unwrap();
+        self.recovery_responses.clear();
+        self.enter_view(view, primary_response.log.unwrap());
+        let mut effects = vec![self.prepare_ok()];
+        effects.extend(self.execute_up_to(primary_response.commit_num));
+        effects
+    }
+
+    fn handle_get_state(&mut self, get_state: GetState) -> Vec<NodeEffect<Message>> {
+        if self.status != Status::Normal
+            || get_state.view != self.view
+            || get_state.op_num > self.op_num()
+        {
+            return Vec::new();
+        }
+        let new_state = NewState {
+            view: self.view,
+            op_num: get_state.op_num,
+            log: self.log[get_state.op_num as usize..].to_vec(),
+            commit_num: self.commit_num,
+        };
+        vec![self.send_replica(get_state.replica_id, Message::NewState(new_state))]
+    }
+
+    fn handle_new_state(&mut self, new_state: NewState) -> Vec<NodeEffect<Message>> {
+        if self.status != Status::Normal
+            || new_state.view != self.view
+            || new_state.op_num != self.op_num()
+        {
+            return Vec::new();
+        }
+        self.log.extend(new_state.log);
+        let mut effects = vec![self.prepare_ok()];
+        effects.extend(self.execute_up_to(new_state.commit_num));
+        effects
+    }
+
+    fn handle_tick(&mut self) -> Vec<NodeEffect<Message>> {
+        self.ticked += 1;
+        match self.status {
+            Status::Normal if self.is_primary() => {
+                if self.ticked < HEARTBEAT_TICKS {
+                    return Vec::new();
+                }
+                self.ticked = 0;
+                let message = match self.log.last() {
+                    // resend the latest preparation, which also lets backups detect whether they
+                    // are missing any earlier one
+                    Some(request) if self.op_num() > self.commit_num => Message::Prepare(Prepare {
+                        view: self.view,
+                        op_num: self.op_num(),
+                        commit_num: self.commit_num,
+                        request: request.clone(),
+                    }),
+                    _ => Message::Commit(Commit {
+                        view: self.view,
+                        commit_num: self.commit_num,
+                    }),
+                };
+                vec![NodeEffect::Broadcast(message)]
+            }
+            Status::Normal | Status::ViewChange => {
+                if self.ticked < VIEW_CHANGE_TICKS {
+                    return Vec::new();
+                }
+                self.start_view_change(self.view + 1)
+            }
+            Status::Recovering => {
+                if self.ticked < VIEW_CHANGE_TICKS {
+                    return Vec::new();
+                }
+                self.ticked = 0;
+                self.recovery()
+            }
+        }
+    }
+
+    fn recovery(&self) -> Vec<NodeEffect<Message>> {
+        vec![NodeEffect::Broadcast(Message::Recovery(Recovery {
+            replica_id: self.id,
+            nonce: self.nonce,
+        }))]
+    }
+}
+
+impl Protocol<NodeEvent<Message>> for Replica {
+    type Effect = Vec<NodeEffect<Message>>;
+
+    fn update(&mut self, event: NodeEvent<Message>) -> Self::Effect {
+        match event {
+            NodeEvent::Init if self.status == Status::Recovering => self.recovery(),
+            NodeEvent::Init => Vec::new(),
+            NodeEvent::Tick => self.handle_tick(),
+            NodeEvent::Handle(Message::Request(request)) => self.handle_request(request),
+            NodeEvent::Handle(Message::Prepare(prepare)) => self.handle_prepare(prepare),
+            NodeEvent::Handle(Message::PrepareOk(prepare_ok)) => self.handle_prepare_ok(prepare_ok),
+            NodeEvent::Handle(Message::Commit(commit)) => self.handle_commit(commit),
+            NodeEvent::Handle(Message::StartViewChange(start_view_change)) => {
+                self.handle_start_view_change(start_view_change)
+            }
+            NodeEvent::Handle(Message::DoViewChange(do_view_change)) => {
+                self.handle_do_view_change(do_view_change)
+            }
+            NodeEvent::Handle(Message::StartView(start_view)) => self.handle_start_view(start_view),
+            NodeEvent::Handle(Message::Recovery(recovery)) => self.handle_recovery(recovery),
+            NodeEvent::Handle(Message::RecoveryResponse(response)) => {
+                self.handle_recovery_response(response)
+            }
+            NodeEvent::Handle(Message::GetState(get_state)) => self.handle_get_state(get_state),
+            NodeEvent::Handle(Message::NewState(new_state)) => self.handle_new_state(new_state),
+            NodeEvent::Handle(Message::Reply(_)) => {
+                UnexpectedMessage("reply").report();
+                Vec::new()
+            }
+            NodeEvent::Timeout(_) => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::hash_map::DefaultHasher,
+        hash::{Hash, Hasher},
+        time::Duration,
+    };
+
+    use crate::{
+        app, linearizability,
+        node::Request,
+        protocol::OneOf,
+        simulate::{
+            cluster::{self, assert_results, histories, replica, replica_mut, workload, Cluster},
+            explore, Choice, PartitionMode,
+        },
+        App,
+        NodeAddr::{TestClient, TestReplica},
+        NodeEffect, NodeEvent, Protocol, Simulate,
+    };
+
+    use super::{Client, Message, Replica, Reply, StartView, Status, VIEW_CHANGE_TICKS};
+
+    fn simulate(
+        num_client: u32,
+        num_replica: u32,
+        num_op: usize,
+    ) -> Cluster<Client, Replica, Message> {
+        cluster::new(
+            num_client,
+            num_replica,
+            num_op,
+            |i, replicas| Client::new(i, TestClient(i), replicas),
+            Replica::new,
+        )
+    }
+
+    #[test]
+    fn single_op() {
+        let mut simulate = simulate(1, 3, 1);
+        simulate.init();
+        while simulate.progress() {}
+        assert_results(&simulate, 1, 1);
+    }
+
+    // a reply from a faulty peer is ignored instead of aborting the replica
+    #[test]
+    fn ignore_reply() {
+        let mut simulate = simulate(1, 3, 1);
+        simulate.init();
+        while simulate.progress() {}
+        let reply = Reply {
+            view: 0,
+            seq: 1,
+            result: b"client 0 op 0".to_vec().into(),
+        };
+        assert!(replica_mut(&mut simulate, 0)
+            .update(NodeEvent::Handle(Message::Reply(reply)))
+            .is_empty());
+    }
+
+    #[test]
+    fn multiple_clients() {
+        let mut simulate = simulate(3, 5, 10);
+        simulate.init();
+        while simulate.progress() {}
+        assert_results(&simulate, 3, 10);
+        assert!(linearizability::check(
+            &mut App::Echo(app::Echo),
+            &histories(&simulate, 3)
+        ));
+        for i in 0..5 {
+            assert_eq!(replica(&simulate, i).op_num(), 30);
+        }
+    }
+
+    #[test]
+    fn random_order() {
+        for seed in 0..20 {
+            let mut simulate = Simulate::with_seed(seed);
+            simulate.nodes = self::simulate(3, 3, 10).nodes;
+            simulate.init();
+            while simulate.progress() {}
+            for i in 0..3 {
+                let ops = (0..10)
+                    .map(|j| format!("client {i} op {j}").into_bytes().into())
+                    .collect::<Vec<Box<[u8]>>>();
+                assert_eq!(workload(&simulate, i).results, ops, "seed {seed}");
+            }
+        }
+    }
+
+    #[test]
+    fn view_change() {
+        let mut simulate = simulate(1, 3, 2);
+        simulate.init();
+        while simulate.progress() {}
+        // backups stop hearing from the primary
+        for _ in 0..10 {
+            simulate.tick(TestReplica(1));
+            simulate.tick(TestReplica(2));
+        }
+        while simulate.progress() {}
+        for i in 0..3 {
+            let replica = replica(&simulate, i);
+            assert_eq!(replica.view, 1);
+            assert_eq!(replica.op_num(), 2);
+        }
+        assert_results(&simulate, 1, 2);
+    }
+
+    #[test]
+    fn isolated_primary() {
+        let mut simulate = simulate(1, 3, 10);
+        simulate.partition(
+            [
+                vec![TestClient(0), TestReplica(1), TestReplica(2)],
+                vec![TestReplica(0)],
+            ],
+            PartitionMode::Drop,
+        );
+        simulate.init();
+        assert!(simulate.run_until_predicate(
+            |simulate| workload(simulate, 0).results.len() == 10,
+            Duration::from_secs(1),
+        ));
+        assert_results(&simulate, 1, 10);
+        assert_eq!(workload(&simulate, 0).node.view, 1);
+    }
+
+    #[test]
+    fn resend_to_new_primary() {
+        let mut simulate = simulate(1, 3, 2);
+        for _ in 0..10 {
+            simulate.tick(TestReplica(1));
+            simulate.tick(TestReplica(2));
+        }
+        while simulate.progress() {}
+        // the client still believes replica 0 is the primary
+        simulate.init();
+        while simulate.progress() {}
+        for _ in 0..2 {
+            simulate.tick(TestClient(0));
+            while simulate.progress() {}
+        }
+        assert_eq!(workload(&simulate, 0).node.view, 1);
+        assert_results(&simulate, 1, 2);
+    }
+
+    #[test]
+    fn recovery() {
+        let mut simulate = simulate(1, 3, 10);
+        simulate.init();
+        while simulate.progress() {}
+        simulate.crash(TestReplica(2));
+        let replicas = (0..3).map(TestReplica).collect();
+        simulate.restart(
+            TestReplica(2),
+            OneOf::B(Replica::recover(2, replicas, App::Echo(app::Echo))),
+        );
+        while simulate.progress() {}
+        let replica = replica(&simulate, 2);
+        assert_eq!(replica.status, Status::Normal);
+        assert_eq!(replica.op_num(), 10);
+    }
+
+    fn fingerprint(simulate: &Cluster<Client, Replica, Message>) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for node in simulate.nodes.values() {
+            match node {
+                OneOf::A(workload) => workload.results.hash(&mut hasher),
+                OneOf::B(replica) => {
+                    (replica.view, replica.commit_num).hash(&mut hasher);
+                    for request in &replica.log {
+                        (request.client_id, request.seq).hash(&mut hasher)
+                    }
+                    let mut acked = replica.acked.iter().collect::<Vec<_>>();
+                    acked.sort();
+                    acked.hash(&mut hasher);
+                }
+            }
+        }
+        hasher.finish()
+    }
+
+    // committed ops are in the same order on every replica
+    fn consistent_commit(simulate: &Cluster<Client, Replica, Message>) -> bool {
+        let committed = simulate
+            .nodes
+            .values()
+            .filter_map(|node| match node {
+                OneOf::A(_) => None,
+                OneOf::B(replica) => Some(
+                    replica.log[..replica.commit_num as usize]
+                        .iter()
+                        .map(|request| request.client_id)
+                        .collect::<Vec<_>>(),
+                ),
+            })
+            .collect::<Vec<_>>();
+        committed.iter().all(|log| {
+            committed
+                .iter()
+                .all(|other| other.starts_with(log) || log.starts_with(other))
+        })
+    }
+
+    // the primary admits a resent request again if a view change dropped it before it committed
+    #[test]
+    fn readmit_dropped_request() {
+        let replicas = (0..3).map(TestReplica).collect();
+        let mut replica = Replica::new(0, replicas, App::Echo(app::Echo));
+        let request = Request {
+            client_id: 0,
+            client_addr: TestClient(0),
+            seq: 1,
+            op: b"hello".to_vec().into(),
+        };
+        replica.update(NodeEvent::Handle(Message::Request(request.clone())));
+        // replica 0 is the primary of view 3 again, whose log does not have the request
+        replica.update(NodeEvent::Handle(Message::StartView(StartView {
+            view: 3,
+            log: Vec::new(),
+            commit_num: 0,
+        })));
+        let effects = replica.update(NodeEvent::Handle(Message::Request(request)));
+        assert!(matches!(
+            &effects[..],
+            [NodeEffect::Broadcast(Message::Prepare(prepare))] if prepare.op_num == 1
+        ));
+    }
+
+    #[test]
+    fn exploration() {
+        let new_simulate = || {
+            let mut simulate = Simulate::default();
+            simulate.nodes = self::simulate(2, 3, 1).nodes;
+            simulate.init();
+            simulate
+        };
+        let result = explore(
+            new_simulate,
+            fingerprint,
+            consistent_commit,
+            10,
+            true,
+            false,
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn explore_view_change() {
+        let new_simulate = || {
+            let mut simulate = Simulate::default();
+            simulate.nodes = self::simulate(0, 3, 0).nodes;
+            simulate.init();
+            simulate.crash(TestReplica(0));
+            simulate
+        };
+        // ticks are only distinguishable by the tick counting states
+        let fingerprint = |simulate: &Cluster<Client, Replica, Message>| {
+            let mut hasher = DefaultHasher::new();
+            for i in 1..3 {
+                let replica = replica(simulate, i);
+                (replica.view, replica.ticked).hash(&mut hasher)
+            }
+            hasher.finish()
+        };
+        let result = explore(
+            new_simulate,
+            fingerprint,
+            |simulate| (1..3).all(|i| replica(simulate, i).view == 0),
+            usize::MAX,
+            false,
+            true,
+        );
+        let Err(choices) = result else { panic!() };
+        assert_eq!(choices.len(), VIEW_CHANGE_TICKS as usize);
+        let mut simulate = new_simulate();
+        for choice in choices {
+            assert!(matches!(choice, Choice::Tick(TestReplica(1 | 2))));
+            simulate.choose(choice)
+        }
+        assert!((1..3).any(|i| replica(&simulate, i).status == Status::ViewChange));
+    }
+}